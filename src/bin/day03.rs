@@ -1,95 +1,384 @@
-use std::collections::HashSet;
+use std::collections::HashMap;
 use std::fs::File;
-use std::io::Read;
+use std::io::{self, BufWriter, Read, Write};
 
-fn part_1() {
-    let mut file = File::open("data/03/input.txt").unwrap();
-    let mut buf = String::new();
-    // pos is (X,Y) of santa clause
-    let mut pos_cache: HashSet<(i32, i32)> = HashSet::new();
-    let mut pos: (i32, i32) = (0, 0);
-    
-    file.read_to_string(&mut buf).unwrap();
-    
-    pos_cache.insert(pos);
-    
-    for ch in buf.chars() {
-        match ch {
-            '>' => {
-                pos.0 += 1;
-                pos_cache.insert(pos);
-            },
-            '<' => {
-                pos.0 -= 1;
-                pos_cache.insert(pos);
-            },
-            '^' => {
-                pos.1 += 1;
-                pos_cache.insert(pos);
+/// Widest bounding box that is still rendered as ASCII view.
+const MAX_ASCII_WIDTH: i32 = 160;
+/// Tallest bounding box that is still rendered as ASCII view.
+const MAX_ASCII_HEIGHT: i32 = 500;
+
+/// Characters of the ASCII heatmap, from one visit up to many visits.
+const HEAT_RAMP: &[u8] = b".:-=+*#%@";
+
+/// One colour per courier for the PPM export; couriers beyond the palette
+/// reuse it from the start.
+const COURIER_COLOURS: [(u8, u8, u8); 6] = [
+    (220, 40, 40),   // santa
+    (40, 120, 220),  // robo-santa
+    (40, 180, 60),
+    (230, 180, 30),
+    (160, 60, 200),
+    (30, 190, 190),
+];
+
+/// All houses visited by the couriers; for every house the number of visits
+/// of each courier is kept.
+struct DeliveryMap {
+    couriers: usize,
+    visits: HashMap<(i32, i32), Vec<u32>>,
+}
+
+impl DeliveryMap {
+    fn new(couriers: usize) -> DeliveryMap {
+        DeliveryMap { couriers, visits: HashMap::new() }
+    }
+
+    fn visit(&mut self, pos: (i32, i32), courier: usize) {
+        let counts = self.visits.entry(pos).or_insert_with(|| vec![0; self.couriers]);
+        counts[courier] += 1;
+    }
+
+    fn houses(&self) -> usize {
+        self.visits.len()
+    }
+
+    fn total(&self, pos: (i32, i32)) -> u32 {
+        self.visits.get(&pos).map_or(0, |c| c.iter().sum())
+    }
+
+    /// Bounding box of all visited houses as (min_x, min_y, max_x, max_y).
+    fn bounds(&self) -> (i32, i32, i32, i32) {
+        let mut b = (0, 0, 0, 0);
+        for &(x, y) in self.visits.keys() {
+            b = (b.0.min(x), b.1.min(y), b.2.max(x), b.3.max(y));
+        }
+        b
+    }
+
+    fn size(&self) -> (usize, usize) {
+        let (min_x, min_y, max_x, max_y) = self.bounds();
+        ((max_x - min_x + 1) as usize, (max_y - min_y + 1) as usize)
+    }
+
+    /// Visits the bounding box row by row, north (^) at the top.
+    fn for_each_cell<F: FnMut((i32, i32), bool) -> io::Result<()>>(&self, mut f: F) -> io::Result<()> {
+        let (min_x, min_y, max_x, max_y) = self.bounds();
+        for y in (min_y..=max_y).rev() {
+            for x in min_x..=max_x {
+                f((x, y), x == max_x)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Plain PGM (P2) heatmap, the gray value is the number of visits.
+    fn write_pgm<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let (width, height) = self.size();
+        let max = self.visits.keys().map(|&p| self.total(p)).max().unwrap_or(1).min(65535);
+        writeln!(out, "P2\n{} {}\n{}", width, height, max)?;
+        self.for_each_cell(|pos, eol| {
+            write!(out, "{}{}", self.total(pos).min(max), if eol { "\n" } else { " " })
+        })
+    }
+
+    /// Plain PPM (P3) with one colour per courier. Houses visited by several
+    /// couriers get the mixed colour of those couriers.
+    fn write_ppm<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let (width, height) = self.size();
+        writeln!(out, "P3\n{} {}\n255", width, height)?;
+        self.for_each_cell(|pos, eol| {
+            let (r, g, b) = self.visits.get(&pos).map_or((0, 0, 0), |c| mix_colours(c));
+            write!(out, "{} {} {}{}", r, g, b, if eol { "\n" } else { "  " })
+        })
+    }
+
+    /// ASCII heatmap, `None` if the map is too large for a terminal.
+    fn ascii(&self) -> Option<String> {
+        let (min_x, min_y, max_x, max_y) = self.bounds();
+        if max_x - min_x >= MAX_ASCII_WIDTH || max_y - min_y >= MAX_ASCII_HEIGHT {
+            return None;
+        }
+        let mut s = String::new();
+        self.for_each_cell(|pos, eol| {
+            s.push(match self.total(pos) {
+                _ if pos == (0, 0) => 'O',
+                0 => ' ',
+                n => HEAT_RAMP[(n as usize - 1).min(HEAT_RAMP.len() - 1)] as char,
+            });
+            if eol {
+                s.push('\n');
+            }
+            Ok(())
+        }).ok()?;
+        Some(s)
+    }
+}
+
+fn mix_colours(counts: &[u32]) -> (u8, u8, u8) {
+    let mut sum = (0u32, 0u32, 0u32);
+    let mut n = 0u32;
+    for (courier, _) in counts.iter().enumerate().filter(|(_, &c)| c > 0) {
+        let (r, g, b) = COURIER_COLOURS[courier % COURIER_COLOURS.len()];
+        sum = (sum.0 + r as u32, sum.1 + g as u32, sum.2 + b as u32);
+        n += 1;
+    }
+    ((sum.0 / n) as u8, (sum.1 / n) as u8, (sum.2 / n) as u8)
+}
+
+/// A single move of a courier.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Move {
+    /// Walk `count` houses in direction (dx, dy), delivering at every house.
+    Step { dx: i32, dy: i32, count: u32 },
+    /// Teleport back to the origin.
+    Home,
+}
+
+/// What to do with characters that are not part of the alphabet.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Unknown {
+    Strict,
+    Skip,
+}
+
+#[derive(Debug, PartialEq)]
+enum RouteError {
+    UnknownChar(usize, char),
+    MissingMove(usize),
+}
+
+impl std::fmt::Display for RouteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RouteError::UnknownChar(pos, ch) => write!(f, "unknown character {:?} at position {}", ch, pos),
+            RouteError::MissingMove(pos) => write!(f, "step count at position {} is not followed by a move", pos),
+        }
+    }
+}
+
+/// The characters a route is written in.
+struct Alphabet {
+    directions: HashMap<char, (i32, i32)>,
+    home: Option<char>,
+    multi_step: bool,
+    unknown: Unknown,
+}
+
+impl Alphabet {
+    /// The puzzle alphabet `><^v`.
+    fn classic() -> Alphabet {
+        let directions = HashMap::from([('>', (1, 0)), ('<', (-1, 0)), ('^', (0, 1)), ('v', (0, -1))]);
+        Alphabet { directions, home: None, multi_step: false, unknown: Unknown::Strict }
+    }
+
+    /// Adds `q e z c` for the diagonals, laid out like the keys around `s`
+    /// on a keyboard.
+    fn with_diagonals(mut self) -> Alphabet {
+        self.directions.extend([('q', (-1, 1)), ('e', (1, 1)), ('z', (-1, -1)), ('c', (1, -1))]);
+        self
+    }
+
+    fn with_home(mut self, ch: char) -> Alphabet {
+        self.home = Some(ch);
+        self
+    }
+
+    /// Allows a decimal step count in front of a move, e.g. `3>`.
+    fn with_multi_step(mut self) -> Alphabet {
+        self.multi_step = true;
+        self
+    }
+
+    fn with_unknown(mut self, unknown: Unknown) -> Alphabet {
+        self.unknown = unknown;
+        self
+    }
+
+    /// Translates a route into moves. Whitespace is always ignored.
+    fn parse(&self, route: &str) -> Result<Vec<Move>, RouteError> {
+        let mut moves = Vec::new();
+        let mut count: Option<(usize, u32)> = None;
+
+        for (pos, ch) in route.chars().enumerate() {
+            if ch.is_whitespace() {
+                continue;
+            }
+            if self.multi_step && ch.is_ascii_digit() {
+                let (start, n) = count.unwrap_or((pos, 0));
+                count = Some((start, n.saturating_mul(10).saturating_add(ch.to_digit(10).unwrap())));
+                continue;
+            }
+            if let Some(&(dx, dy)) = self.directions.get(&ch) {
+                moves.push(Move::Step { dx, dy, count: count.take().map_or(1, |c| c.1) });
+            } else if Some(ch) == self.home {
+                // home has no step count
+                match count.take() {
+                    Some((start, _)) if self.unknown == Unknown::Strict => return Err(RouteError::MissingMove(start)),
+                    _ => moves.push(Move::Home),
+                }
+            } else if self.unknown == Unknown::Strict {
+                return Err(RouteError::UnknownChar(pos, ch));
+            }
+        }
+        match count {
+            Some((pos, _)) if self.unknown == Unknown::Strict => Err(RouteError::MissingMove(pos)),
+            _ => Ok(moves),
+        }
+    }
+}
+
+/// Lets the couriers take turns on the moves of the route, all start at the
+/// origin.
+fn deliver(moves: &[Move], couriers: usize) -> DeliveryMap {
+    let mut map = DeliveryMap::new(couriers);
+    let mut pos: Vec<(i32, i32)> = vec![(0, 0); couriers];
+
+    for courier in 0..couriers {
+        map.visit((0, 0), courier);
+    }
+    for (turn, m) in moves.iter().enumerate() {
+        let courier = turn % couriers;
+        match *m {
+            Move::Step { dx, dy, count } => {
+                for _ in 0..count {
+                    pos[courier].0 += dx;
+                    pos[courier].1 += dy;
+                    map.visit(pos[courier], courier);
+                }
             },
-            'v' => {
-                pos.1 -= 1;
-                pos_cache.insert(pos);
+            Move::Home => {
+                pos[courier] = (0, 0);
+                map.visit(pos[courier], courier);
             },
-            _ => panic!("Unknown character")
         }
     }
-    
-    println!("Part 1: {}", pos_cache.len());
+    map
 }
 
-fn part_2() {
-    let mut file = File::open("data/03/input.txt").unwrap();
+fn read_input(filename: &str) -> String {
+    let mut file = File::open(filename).unwrap();
     let mut buf = String::new();
-    // pos is (X,Y) of santa clause
-    let mut pos_cache: HashSet<(i32, i32)> = HashSet::new();
-    let mut pos_s: (i32, i32) = (0, 0);
-    let mut pos_r: (i32, i32) = (0, 0);
-    let mut turn_santa: bool = true;
-    
     file.read_to_string(&mut buf).unwrap();
-    
-    pos_cache.insert(pos_s);
-    pos_cache.insert(pos_r);
-    
-
-    for ch in buf.chars() {
-        let mut pos: (i32, i32);
-
-        if turn_santa {
-            pos = pos_s
-        } else {
-            pos = pos_r
-        }
-        
-        match ch {
-            '>' => {
-                pos.0 += 1;
-            },
-            '<' => {
-                pos.0 -= 1;
+    buf
+}
+
+fn export(map: &DeliveryMap, filename: &str, ppm: bool) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(filename)?);
+    if ppm {
+        map.write_ppm(&mut out)
+    } else {
+        map.write_pgm(&mut out)
+    }
+}
+
+/// Usage: day03 [--input FILE] [--diagonal] [--multi-step] [--home CH]
+///              [--skip-unknown] [--couriers N] [--pgm FILE] [--ppm FILE]
+///              [--ascii]
+///
+/// Both parts of the puzzle are solved with the chosen alphabet. The export
+/// options render the map of the given number of couriers (default 2).
+fn main () {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mut input = "data/03/input.txt".to_owned();
+    let mut alphabet = Alphabet::classic();
+    let mut couriers = 2;
+    let mut exports: Vec<(String, Option<String>)> = Vec::new();
+
+    let mut i = 0;
+    while i < args.len() {
+        let value = args.get(i + 1).cloned();
+        match (args[i].as_str(), value) {
+            ("--input", Some(f)) => { input = f; i += 1; },
+            ("--diagonal", _) => alphabet = alphabet.with_diagonals(),
+            ("--multi-step", _) => alphabet = alphabet.with_multi_step(),
+            ("--home", Some(ch)) => {
+                alphabet = alphabet.with_home(ch.chars().next().expect("home character"));
+                i += 1;
             },
-            '^' => {
-                pos.1 += 1;
+            ("--skip-unknown", _) => alphabet = alphabet.with_unknown(Unknown::Skip),
+            ("--couriers", Some(n)) => {
+                couriers = n.parse().ok().filter(|&n| n > 0).expect("number of couriers, at least 1");
+                i += 1;
             },
-            'v' => {
-                pos.1 -= 1;
+            ("--pgm", Some(f)) | ("--ppm", Some(f)) => {
+                exports.push((args[i].clone(), Some(f)));
+                i += 1;
             },
-            _ => panic!("Unknown character")
+            ("--ascii", _) => exports.push((args[i].clone(), None)),
+            (a, _) => panic!("Unknown argument {}", a),
         }
-        pos_cache.insert(pos);
-        if turn_santa {
-            pos_s = pos;
-        } else {
-            pos_r = pos;
+        i += 1;
+    }
+
+    let moves = match alphabet.parse(&read_input(&input)) {
+        Ok(m) => m,
+        Err(e) => {
+            eprintln!("{}: {}", input, e);
+            std::process::exit(1);
+        },
+    };
+    println!("Part 1: {}", deliver(&moves, 1).houses());
+    println!("Part 2: {}", deliver(&moves, 2).houses());
+
+    let map = deliver(&moves, couriers);
+    for (kind, file) in exports {
+        match (kind.as_str(), file) {
+            ("--ascii", _) => match map.ascii() {
+                Some(s) => print!("{}", s),
+                None => println!("Map is too large for an ASCII view"),
+            },
+            (kind, Some(f)) => export(&map, &f, kind == "--ppm").expect("export failed"),
+            _ => (),
         }
-        turn_santa = !turn_santa;
     }
-    
-    println!("Part 2: {}", pos_cache.len());
 }
 
-fn main () {
-    part_1();
-    part_2();
-}
\ No newline at end of file
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn houses(route: &str, alphabet: &Alphabet, couriers: usize) -> usize {
+        deliver(&alphabet.parse(route).unwrap(), couriers).houses()
+    }
+
+    #[test]
+    fn test_classic() {
+        let a = Alphabet::classic();
+        assert_eq!(houses(">", &a, 1), 2);
+        assert_eq!(houses("^>v<", &a, 1), 4);
+        assert_eq!(houses("^v^v^v^v^v", &a, 1), 2);
+        assert_eq!(houses("^v", &a, 2), 3);
+        assert_eq!(houses("^>v<", &a, 2), 3);
+        assert_eq!(houses("^v^v^v^v^v", &a, 2), 11);
+        assert_eq!(a.parse("^x"), Err(RouteError::UnknownChar(1, 'x')));
+    }
+
+    #[test]
+    fn test_extended() {
+        let a = Alphabet::classic().with_diagonals().with_multi_step().with_home('o');
+        assert_eq!(houses("ec", &a, 1), 3);
+        assert_eq!(houses("3>o3<", &a, 1), 7);
+        assert_eq!(houses("12^", &a, 2), 13);
+        assert_eq!(a.parse("3"), Err(RouteError::MissingMove(0)));
+        assert_eq!(a.parse(">12o"), Err(RouteError::MissingMove(1)));
+        let a = a.with_unknown(Unknown::Skip);
+        assert_eq!(a.parse("x>y"), Ok(vec![Move::Step { dx: 1, dy: 0, count: 1 }]));
+        assert_eq!(a.parse("3o"), Ok(vec![Move::Home]));
+    }
+
+    #[test]
+    fn test_exports() {
+        let map = deliver(&Alphabet::classic().parse("^>").unwrap(), 2);
+        let mut pgm = Vec::new();
+        map.write_pgm(&mut pgm).unwrap();
+        assert_eq!(String::from_utf8(pgm).unwrap(), "P2\n2 2\n2\n1 0\n2 1\n");
+        // the origin is shared by both couriers and gets their mixed colour
+        let mut ppm = Vec::new();
+        map.write_ppm(&mut ppm).unwrap();
+        assert_eq!(String::from_utf8(ppm).unwrap(), "P3\n2 2\n255\n220 40 40  0 0 0\n130 80 130  40 120 220\n");
+        assert_eq!(map.ascii().unwrap(), ". \nO.\n");
+        assert_eq!(deliver(&Alphabet::classic().parse(&">".repeat(200)).unwrap(), 1).ascii(), None);
+        assert_eq!(deliver(&Alphabet::classic().parse(&"^".repeat(100_000)).unwrap(), 1).ascii(), None);
+    }
+}
@@ -18,32 +18,446 @@
 //; an MD5 hash starting with five zeroes is 1048970; that is, the MD5 hash of
 //; pqrstuv1048970 looks like 000006136ef....
 
-use std::fmt::Write;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
 use md5::{Digest, Md5};
+use serde::{Deserialize, Serialize};
 
-const secret_key: &str = include_str!("../../data/04/input.txt");
+const SECRET_KEY: &str = include_str!("../../data/04/input.txt");
 
-fn part1(start: &str) -> u64 {
-    let mut hash = Md5::new();
-    for i in 0_u64.. {
-        let s = format!("{}{}", secret_key, i);
-        let mut t = String::new();
-        hash.update(&s);
-        let dig = hash.finalize_reset(); 
-        let a: &[u8] = dig.as_ref();
-        for &ch in a {
-            write!(&mut t, "{:02X}", ch);
+/// Number of nonces a worker claims at once.
+const CHUNK_SIZE: u64 = 1 << 14;
+
+/// Number of chunks per worker that are searched between two checkpoints.
+const BATCH_CHUNKS: u64 = 16;
+
+/// Minimal time between two writes of the checkpoint file.
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Number of hashes for measuring the throughput.
+const BENCH_NONCES: u64 = 1 << 22;
+
+/// Writes `n` in decimal to the end of `buf` and returns the digits.
+fn decimal(buf: &mut [u8; 20], mut n: u64) -> &[u8] {
+    let mut idx = buf.len();
+    loop {
+        idx -= 1;
+        buf[idx] = b'0' + (n % 10) as u8;
+        n /= 10;
+        if n == 0 {
+            break;
+        }
+    }
+    &buf[idx..]
+}
+
+/// When a hash counts as a found AdventCoin.
+#[derive(Debug, Clone, PartialEq)]
+enum Difficulty {
+    /// The digest starts with this many zero bits.
+    ZeroBits(u32),
+    /// The hex representation of the digest starts with these nibbles.
+    HexPrefix(Vec<u8>),
+    /// The digest, read as big-endian number, is below the target. A short
+    /// target is padded with zero bytes to the length of the digest.
+    Below(Vec<u8>),
+}
+
+fn parse_nibbles(s: &str) -> Result<Vec<u8>, String> {
+    s.chars()
+        .map(|c| c.to_digit(16).map(|d| d as u8).ok_or(format!("{:?} is not a hex digit", c)))
+        .collect()
+}
+
+impl Difficulty {
+    /// Parses `bits:N`, `hex:PREFIX` or `below:TARGET`. A bare hex string is
+    /// taken as prefix, like `00000` for the puzzle.
+    fn parse(spec: &str) -> Result<Difficulty, String> {
+        match spec.split_once(':') {
+            Some(("bits", n)) => n.parse().map(Difficulty::ZeroBits).map_err(|e| format!("{}: {}", spec, e)),
+            Some(("hex", p)) => parse_nibbles(p).map(Difficulty::HexPrefix),
+            Some(("below", t)) => {
+                let mut nibbles = parse_nibbles(t)?;
+                if nibbles.iter().all(|&n| n == 0) {
+                    return Err(format!("{}: no digest is below an empty or zero target", spec));
+                }
+                if nibbles.len() % 2 == 1 {
+                    nibbles.push(0);
+                }
+                Ok(Difficulty::Below(nibbles.chunks(2).map(|c| (c[0] << 4) | c[1]).collect()))
+            },
+            Some((kind, _)) => Err(format!("unknown difficulty {:?}", kind)),
+            None => parse_nibbles(spec).map(Difficulty::HexPrefix),
+        }
+    }
+
+    fn matches(&self, digest: &[u8]) -> bool {
+        match self {
+            Difficulty::ZeroBits(bits) => {
+                let bytes = (*bits / 8) as usize;
+                let rest = bits % 8;
+                digest[..bytes].iter().all(|&b| b == 0) && (rest == 0 || digest[bytes] >> (8 - rest) == 0)
+            },
+            Difficulty::HexPrefix(nibbles) => nibbles.iter().enumerate().all(|(i, &n)| {
+                let b = digest[i / 2];
+                n == if i % 2 == 0 { b >> 4 } else { b & 0x0f }
+            }),
+            Difficulty::Below(target) => digest[..target.len()] < target[..],
+        }
+    }
+
+    /// Number of leading digest bits the difficulty looks at.
+    fn bits(&self) -> usize {
+        match self {
+            Difficulty::ZeroBits(bits) => *bits as usize,
+            Difficulty::HexPrefix(nibbles) => nibbles.len() * 4,
+            Difficulty::Below(target) => target.len() * 8,
+        }
+    }
+
+    /// Expected number of hashes until a match, assuming uniformly
+    /// distributed digests.
+    fn expected_trials(&self) -> f64 {
+        match self {
+            Difficulty::ZeroBits(bits) => 2f64.powi(*bits as i32),
+            Difficulty::HexPrefix(nibbles) => 16f64.powi(nibbles.len() as i32),
+            Difficulty::Below(target) => {
+                let p = target.iter().rev().fold(0.0, |p, &b| (p + b as f64) / 256.0);
+                1.0 / p
+            },
         }
-        if t.starts_with(start) {
-            return i;
+    }
+}
+
+impl std::fmt::Display for Difficulty {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Difficulty::ZeroBits(bits) => write!(f, "bits:{}", bits),
+            Difficulty::HexPrefix(nibbles) => {
+                write!(f, "hex:")?;
+                nibbles.iter().try_for_each(|n| write!(f, "{:x}", n))
+            },
+            Difficulty::Below(target) => {
+                write!(f, "below:")?;
+                target.iter().try_for_each(|b| write!(f, "{:02x}", b))
+            },
         }
-        t.truncate(0);
     }
-    0_u64
 }
 
+/// All nonces in `range` whose hash meets the difficulty. The hasher
+/// already contains the secret key.
+fn search_chunk<D: Digest + Clone>(keyed: &D, range: Range<u64>, difficulty: &Difficulty) -> Vec<u64> {
+    let mut buf = [0u8; 20];
+    range.filter(|&nonce| {
+        let mut hash = keyed.clone();
+        hash.update(decimal(&mut buf, nonce));
+        difficulty.matches(&hash.finalize())
+    }).collect()
+}
+
+/// State of a search: every nonce below `checked` has been hashed and the
+/// matching ones are listed in `found` in ascending order.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+struct Progress {
+    checked: u64,
+    found: Vec<u64>,
+}
+
+/// Continues the search until at least `wanted` nonces are found. The nonce
+/// space is processed in batches of chunks; within a batch the workers
+/// claim the chunks in any order, but a batch is only merged into the
+/// progress once it is complete, so the progress never has holes. After
+/// every batch `on_batch` is called, e.g. to write a checkpoint; the search
+/// stops early if it returns false.
+fn search<D>(key: &str, difficulty: &Difficulty, workers: usize, progress: &mut Progress, wanted: usize, on_batch: &mut dyn FnMut(&Progress) -> bool)
+where D: Digest + Clone + Sync, {
+    let mut keyed = D::new();
+    keyed.update(key.trim());
+    let workers = workers.max(1);
+    let batch_size = CHUNK_SIZE * BATCH_CHUNKS * workers as u64;
+
+    while progress.found.len() < wanted {
+        let batch = progress.checked..progress.checked.saturating_add(batch_size);
+        let next_chunk = AtomicU64::new(batch.start);
+        let found = Mutex::new(Vec::new());
+
+        thread::scope(|s| {
+            for _ in 0..workers {
+                s.spawn(|| loop {
+                    let start = next_chunk.fetch_add(CHUNK_SIZE, Ordering::Relaxed);
+                    if start >= batch.end {
+                        break;
+                    }
+                    let matches = search_chunk(&keyed, start..(start + CHUNK_SIZE).min(batch.end), difficulty);
+                    found.lock().unwrap().extend(matches);
+                });
+            }
+        });
+        let mut found = found.into_inner().unwrap();
+        found.sort_unstable();
+        progress.found.extend(found);
+        progress.checked = batch.end;
+        if !on_batch(progress) || batch.end == u64::MAX {
+            break;
+        }
+    }
+}
+
+/// Searches the lowest `wanted` nonces from scratch. The workers claim
+/// chunks of the nonce space in ascending order and stop once every chunk
+/// below the `wanted`-th lowest match found so far has been checked, so the
+/// result is the same as for a sequential search.
+fn mine_lowest<D>(key: &str, difficulty: &Difficulty, workers: usize, wanted: usize) -> Vec<u64>
+where D: Digest + Clone + Sync, {
+    if wanted == 0 {
+        return Vec::new();
+    }
+    let mut keyed = D::new();
+    keyed.update(key.trim());
+    let next_chunk = AtomicU64::new(0);
+    let limit = AtomicU64::new(u64::MAX);
+    let found = Mutex::new(Vec::new());
+
+    thread::scope(|s| {
+        for _ in 0..workers.max(1) {
+            s.spawn(|| loop {
+                let start = next_chunk.fetch_add(CHUNK_SIZE, Ordering::Relaxed);
+                if start >= limit.load(Ordering::Relaxed) {
+                    break;
+                }
+                let matches = search_chunk(&keyed, start..start.saturating_add(CHUNK_SIZE), difficulty);
+                if !matches.is_empty() {
+                    let mut found = found.lock().unwrap();
+                    found.extend(matches);
+                    found.sort_unstable();
+                    if found.len() >= wanted {
+                        limit.fetch_min(found[wanted - 1], Ordering::Relaxed);
+                    }
+                }
+            });
+        }
+    });
+    let mut found = found.into_inner().unwrap();
+    found.truncate(wanted);
+    found
+}
+
+type SearchFn = fn(&str, &Difficulty, usize, &mut Progress, usize, &mut dyn FnMut(&Progress) -> bool);
+type MineFn = fn(&str, &Difficulty, usize, usize) -> Vec<u64>;
+
+/// A hash algorithm the miner can use.
+struct Hash {
+    name: &'static str,
+    output_size: fn() -> usize,
+    search: SearchFn,
+    lowest: MineFn,
+}
+
+/// All hash algorithms with a local implementation. Every `Digest` can be
+/// added here.
+const HASHES: &[Hash] = &[
+    Hash { name: "md5", output_size: <Md5 as Digest>::output_size, search: search::<Md5>, lowest: mine_lowest::<Md5> },
+];
 
+impl Hash {
+    fn by_name(name: &str) -> Result<&'static Hash, String> {
+        HASHES.iter().find(|h| h.name == name).ok_or_else(|| {
+            let known: Vec<&str> = HASHES.iter().map(|h| h.name).collect();
+            format!("unknown hash {:?}, known are {}", name, known.join(", "))
+        })
+    }
+
+    /// Guards against difficulties that need more bits than the digest has.
+    fn check(&self, difficulty: &Difficulty) -> Result<(), String> {
+        if difficulty.bits() > (self.output_size)() * 8 {
+            return Err(format!("{} needs more bits than {} provides", difficulty, self.name));
+        }
+        Ok(())
+    }
+
+    /// Searches the lowest `wanted` nonces from scratch with `workers`
+    /// threads.
+    fn mine(&self, key: &str, difficulty: &Difficulty, workers: usize, wanted: usize) -> Vec<u64> {
+        (self.lowest)(key, difficulty, workers, wanted)
+    }
+
+    /// Hashes per second on the secret key.
+    fn throughput(&self, key: &str, workers: usize) -> f64 {
+        let never = Difficulty::Below(vec![0]);
+        let mut progress = Progress::default();
+        let start = Instant::now();
+        (self.search)(key, &never, workers, &mut progress, 1, &mut |p| p.checked < BENCH_NONCES);
+        progress.checked as f64 / start.elapsed().as_secs_f64()
+    }
+}
+
+/// Progress of all searches, keyed by secret key, hash and difficulty.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Checkpoint {
+    searches: HashMap<String, Progress>,
+}
+
+impl Checkpoint {
+    fn load(filename: &Path) -> io::Result<Checkpoint> {
+        match File::open(filename) {
+            Ok(file) => Ok(serde_json::from_reader(BufReader::new(file))?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Checkpoint::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Writes to a temporary file first, so an interrupted run never leaves
+    /// a broken checkpoint behind.
+    fn save(&self, filename: &Path) -> io::Result<()> {
+        let tmp = filename.with_extension("tmp");
+        let mut w = BufWriter::new(File::create(&tmp)?);
+        serde_json::to_writer_pretty(&mut w, self)?;
+        w.flush()?;
+        w.get_ref().sync_all()?;
+        fs::rename(tmp, filename)
+    }
+
+    fn entry(&mut self, key: &str, hash: &Hash, difficulty: &Difficulty) -> &mut Progress {
+        self.searches.entry(format!("{}/{}/{}", key.trim(), hash.name, difficulty)).or_default()
+    }
+}
+
+/// Runs a search that is resumed from and saved to the checkpoint file.
+fn resumable_search(filename: &Path, hash: &Hash, key: &str, difficulty: &Difficulty, workers: usize, wanted: usize) -> io::Result<Progress> {
+    let mut checkpoint = Checkpoint::load(filename)?;
+    let mut progress = checkpoint.entry(key, hash, difficulty).clone();
+    let mut last_save = Instant::now();
+    let mut result = Ok(());
+
+    (hash.search)(key, difficulty, workers, &mut progress, wanted, &mut |p| {
+        if last_save.elapsed() >= CHECKPOINT_INTERVAL {
+            *checkpoint.entry(key, hash, difficulty) = p.clone();
+            result = checkpoint.save(filename);
+            last_save = Instant::now();
+        }
+        result.is_ok()
+    });
+    result?;
+    *checkpoint.entry(key, hash, difficulty) = progress.clone();
+    checkpoint.save(filename)?;
+    Ok(progress)
+}
+
+
+/// Usage: day04 [--hash NAME] [--checkpoint FILE] [--matches N] [SPEC...]
+///        day04 --bench
+///
+/// Without specs both parts of the puzzle are solved, otherwise the secret
+/// key is mined for every difficulty spec (see `Difficulty::parse`). With a
+/// checkpoint file the search resumes where the last run stopped, until at
+/// least N matches are known, and all nonces found so far are listed.
+/// Otherwise the lowest N nonces (default 1) are listed.
+/// `--bench` measures the throughput of all known hashes.
 fn main() {
-    println!("{}", part1("00000"));
-    println!("{}", part1("000000"));
+    let workers = thread::available_parallelism().map_or(1, |n| n.get());
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mut hash = &HASHES[0];
+    let mut checkpoint: Option<PathBuf> = None;
+    let mut wanted = 1;
+    let mut specs: Vec<String> = Vec::new();
+
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--hash" => {
+                match Hash::by_name(args.get(i + 1).map_or("", String::as_str)) {
+                    Ok(h) => hash = h,
+                    Err(e) => { eprintln!("{}", e); std::process::exit(1); },
+                }
+                i += 1;
+            },
+            "--bench" => {
+                for h in HASHES {
+                    println!("{}: {:.2} MH/s", h.name, h.throughput(SECRET_KEY, workers) / 1e6);
+                }
+                return;
+            },
+            "--checkpoint" => { checkpoint = args.get(i + 1).map(PathBuf::from); i += 1; },
+            "--matches" => { wanted = args.get(i + 1).and_then(|n| n.parse().ok()).expect("number of matches"); i += 1; },
+            spec => specs.push(spec.to_owned()),
+        }
+        i += 1;
+    }
+    if specs.is_empty() {
+        specs = vec!["00000".to_owned(), "000000".to_owned()];
+    }
+    for spec in specs {
+        let d = match Difficulty::parse(&spec).and_then(|d| hash.check(&d).map(|_| d)) {
+            Ok(d) => d,
+            Err(e) => { eprintln!("{}", e); continue; },
+        };
+        let progress = match &checkpoint {
+            Some(f) => resumable_search(f, hash, SECRET_KEY, &d, workers, wanted).expect("checkpoint failed"),
+            None => Progress { checked: 0, found: hash.mine(SECRET_KEY, &d, workers, wanted) },
+        };
+        if checkpoint.is_some() {
+            let found: Vec<String> = progress.found.iter().map(u64::to_string).collect();
+            println!("{} (expected {:.0} trials, checked {}): {}", d, d.expected_trials(), progress.checked, found.join(", "));
+        } else {
+            let found: Vec<String> = progress.found.iter().take(wanted).map(u64::to_string).collect();
+            println!("{} (expected {:.0} trials): {}", d, d.expected_trials(), found.join(", "));
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_difficulty() {
+        let d = Difficulty::parse("00000").unwrap();
+        assert!(d.matches(&[0x00, 0x00, 0x0f]));
+        assert!(!d.matches(&[0x00, 0x00, 0x1f]));
+        let d = Difficulty::parse("bits:21").unwrap();
+        assert!(d.matches(&[0x00, 0x00, 0x07]));
+        assert!(!d.matches(&[0x00, 0x00, 0x08]));
+        let d = Difficulty::parse("hex:00abc").unwrap();
+        assert!(d.matches(&[0x00, 0xab, 0xcf]));
+        assert!(!d.matches(&[0x00, 0xab, 0xdf]));
+        let d = Difficulty::parse("below:0001").unwrap();
+        assert!(d.matches(&[0x00, 0x00, 0xff]));
+        assert!(!d.matches(&[0x00, 0x01, 0x00]));
+        assert_eq!(d.expected_trials(), 65536.0);
+        assert_eq!(Difficulty::parse("bits:20").unwrap().expected_trials(), 1048576.0);
+        assert!(Difficulty::parse("hex:0g").is_err());
+        assert!(Difficulty::parse("below:").is_err());
+        assert!(Difficulty::parse("below:000").is_err());
+    }
+
+    #[test]
+    fn test_mine() {
+        let md5 = Hash::by_name("md5").unwrap();
+        assert_eq!(md5.mine("abcdef", &Difficulty::ZeroBits(20), 4, 1)[0], 609043);
+        assert!(md5.check(&Difficulty::ZeroBits(129)).is_err());
+        assert!(Hash::by_name("sha0").is_err());
+    }
+
+    #[test]
+    fn test_resume() {
+        let d = Difficulty::parse("000").unwrap();
+        let full = HASHES[0].mine("abcdef", &d, 2, 3);
+        assert_eq!(full.len(), 3);
+        assert_eq!(HASHES[0].mine("abcdef", &d, 4, 1), full[..1]);
+
+        let mut resumed = Progress::default();
+        search::<Md5>("abcdef", &d, 3, &mut resumed, 1, &mut |_| true);
+        search::<Md5>("abcdef", &d, 1, &mut resumed, 3, &mut |_| true);
+        assert_eq!(full[..3], resumed.found[..3]);
+        assert!(full.windows(2).all(|w| w[0] < w[1]));
+    }
 }
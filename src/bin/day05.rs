@@ -1,80 +1,380 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
 use std::fs::File;
-use std::io::{BufReader, BufRead, Lines};
+use std::io::{self, BufReader, BufRead, Read};
 
-const VOWEL: &str = "aeiou";
+/// A primitive check on a string. Each primitive yields the positions (in
+/// characters) where it fires; the rule then compares their number with a
+/// threshold.
+#[derive(Debug, Clone, PartialEq)]
+enum Primitive {
+    /// `count CHARS`: every character out of the set, e.g. `count aeiou`.
+    Count(String),
+    /// `double`: a letter directly followed by the same letter.
+    Double,
+    /// `contains STR...`: every occurrence of one of the strings.
+    Contains(Vec<String>),
+    /// `repeated_pair`: a pair of letters that already appeared before
+    /// without overlapping, like `xy` in `xyxy`.
+    RepeatedPair,
+    /// `split_double`: a letter that repeats with one letter in between,
+    /// like `xyx`.
+    SplitDouble,
+}
 
-fn part_1() {
-    let file = File::open("data/05/input.txt").unwrap();
-    let reader = BufReader::new(file);
-    let mut lines = reader.lines();
-    let mut good_counter: u32 = 0;
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Cmp {
+    AtLeast,
+    AtMost,
+    Exactly,
+}
 
-    while let Some(Ok(line)) = (&mut lines).next() {
-        let mut vowels_count: u32 = 0;
-        let mut double: bool = false;
-        let mut bad: bool = false;
-        let mut last_char: char = ' ';
+/// A primitive with a threshold, e.g. `count aeiou >= 3`.
+#[derive(Debug, Clone, PartialEq)]
+struct Rule {
+    text: String,
+    primitive: Primitive,
+    cmp: Cmp,
+    threshold: usize,
+}
 
-        for ch in line.chars() {
-            if VOWEL.contains(ch) {
-                vowels_count += 1;
+/// All rules a string must pass to be nice.
+#[derive(Debug, Clone, PartialEq)]
+struct RuleSet {
+    name: String,
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug, PartialEq)]
+struct ParseError {
+    line: usize,
+    msg: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.msg)
+    }
+}
+
+impl Rule {
+    /// Parses `<primitive> [args] [>=|<=|== N]`. Without a threshold the
+    /// primitive has to fire at least once.
+    fn parse(text: &str) -> Result<Rule, String> {
+        let mut words: Vec<&str> = text.split_whitespace().collect();
+        let (mut cmp, mut threshold) = (Cmp::AtLeast, 1);
+
+        if words.len() >= 3 {
+            let c = match words[words.len() - 2] {
+                ">=" => Some(Cmp::AtLeast),
+                "<=" => Some(Cmp::AtMost),
+                "==" => Some(Cmp::Exactly),
+                _ => None,
+            };
+            if let Some(c) = c {
+                cmp = c;
+                threshold = words[words.len() - 1].parse().map_err(|_| format!("bad threshold {:?}", words[words.len() - 1]))?;
+                words.truncate(words.len() - 2);
             }
-            double |= ch == last_char;
-            bad |= (last_char == 'a' && ch == 'b') ||
-                    (last_char == 'c' && ch == 'd') ||
-                    (last_char == 'p' && ch == 'q') ||
-                    (last_char == 'x' && ch == 'y');
-            last_char = ch;
         }
-        if vowels_count > 2 && double && !bad {
-            good_counter += 1;
+        let primitive = match (words[0], &words[1..]) {
+            ("count", [set]) => Primitive::Count(set.to_string()),
+            ("double", []) => Primitive::Double,
+            ("contains", strs) if !strs.is_empty() => Primitive::Contains(strs.iter().map(|s| s.to_string()).collect()),
+            ("repeated_pair", []) => Primitive::RepeatedPair,
+            ("split_double", []) => Primitive::SplitDouble,
+            (p, _) => return Err(format!("unknown primitive or wrong arguments: {:?}", p)),
+        };
+        Ok(Rule { text: text.trim().to_owned(), primitive, cmp, threshold })
+    }
+
+    fn passes(&self, count: usize) -> bool {
+        match self.cmp {
+            Cmp::AtLeast => count >= self.threshold,
+            Cmp::AtMost => count <= self.threshold,
+            Cmp::Exactly => count == self.threshold,
         }
     }
+}
+
+/// Marks pairs in `Matcher::ascii_pairs` that have not been seen yet.
+const UNSEEN: usize = usize::MAX;
 
-    println!("Good: {}", good_counter);
+/// Automaton that checks one primitive while the characters of a string
+/// stream by. Besides the last characters it only keeps a window as long as
+/// the longest `contains` string and, for `repeated_pair`, the first
+/// position of every distinct pair. ASCII pairs live in a fixed 128×128
+/// table, so for ASCII input memory does not grow with the string; pairs
+/// with other characters go to a map that grows with their number.
+struct Matcher<'a> {
+    primitive: &'a Primitive,
+    pos: usize,
+    last: [Option<char>; 2],
+    window: VecDeque<char>,
+    ascii_pairs: Vec<usize>,
+    /// Entries of `ascii_pairs` to clear on `reset`.
+    touched: Vec<usize>,
+    pairs: HashMap<(char, char), usize>,
 }
 
+impl<'a> Matcher<'a> {
+    fn new(primitive: &'a Primitive) -> Matcher<'a> {
+        let ascii_pairs = if *primitive == Primitive::RepeatedPair { vec![UNSEEN; 128 * 128] } else { Vec::new() };
+        Matcher { primitive, pos: 0, last: [None, None], window: VecDeque::new(), ascii_pairs, touched: Vec::new(), pairs: HashMap::new() }
+    }
+
+    fn reset(&mut self) {
+        self.pos = 0;
+        self.last = [None, None];
+        self.window.clear();
+        for &idx in &self.touched {
+            self.ascii_pairs[idx] = UNSEEN;
+        }
+        self.touched.clear();
+        self.pairs.clear();
+    }
 
-fn part_2() {
-    let file: File = File::open("data/05/input.txt").unwrap();
-    let reader: BufReader<File> = BufReader::new(file);
-    let mut lines: Lines<BufReader<File>> = reader.lines();
-    let mut good_count: u32 = 0;
+    /// Position of the first occurrence of the pair, which is recorded at
+    /// `pos` if it is new.
+    fn first_pair(&mut self, pair: (char, char), pos: usize) -> usize {
+        if pair.0.is_ascii() && pair.1.is_ascii() {
+            let idx = pair.0 as usize * 128 + pair.1 as usize;
+            if self.ascii_pairs[idx] == UNSEEN {
+                self.ascii_pairs[idx] = pos;
+                self.touched.push(idx);
+            }
+            self.ascii_pairs[idx]
+        } else {
+            *self.pairs.entry(pair).or_insert(pos)
+        }
+    }
 
-    while let Some(Ok(line)) = lines.next() {
-        let mut pairs = HashMap::new();
-        let mut split_double: bool = false;
-        let mut double_pair: bool = false;
+    /// Feeds the next character; returns the position (in characters) where
+    /// the primitive starts if it fires with this character.
+    fn feed(&mut self, ch: char) -> Option<usize> {
+        let pos = self.pos;
+        let [prev, before] = self.last;
+        let fired = match self.primitive {
+            Primitive::Count(set) => set.contains(ch).then_some(pos),
+            Primitive::Double => (prev == Some(ch)).then(|| pos - 1),
+            Primitive::Contains(strs) => {
+                let longest = strs.iter().map(|p| p.chars().count()).max().unwrap_or(0);
+                self.window.push_back(ch);
+                if self.window.len() > longest {
+                    self.window.pop_front();
+                }
+                strs.iter().find(|p| {
+                    let n = p.chars().count();
+                    n <= self.window.len() && self.window.iter().skip(self.window.len() - n).copied().eq(p.chars())
+                }).map(|p| pos + 1 - p.chars().count())
+            },
+            Primitive::RepeatedPair => prev.and_then(|prev| {
+                let first = self.first_pair((prev, ch), pos - 1);
+                (first + 2 < pos).then(|| pos - 1)
+            }),
+            Primitive::SplitDouble => (before == Some(ch)).then(|| pos - 2),
+        };
+        self.last = [Some(ch), prev];
+        self.pos += 1;
+        fired
+    }
+}
 
-        // Positon, an dem das Paar erstmalig auftritt
-        pairs.insert(&line[0..2], 0);
+/// Parses a rule file. A line `[name]` starts a new rule set, every other
+/// non-empty line is a rule of the current set. `#` starts a comment.
+fn parse_rule_sets(text: &str) -> Result<Vec<RuleSet>, ParseError> {
+    let mut sets: Vec<RuleSet> = Vec::new();
 
-        for idx in 1..(line.len()-1) {
-            let key = &line[idx..idx+2];
-            if let Some(pos) = pairs.get_mut(key) {
-                if  *pos + 2 <= idx {
-                    double_pair |= true;
+    for (idx, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        let err = |msg: String| ParseError { line: idx + 1, msg };
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            sets.push(RuleSet { name: name.trim().to_owned(), rules: Vec::new() });
+        } else {
+            let rule = Rule::parse(line).map_err(err)?;
+            match sets.last_mut() {
+                Some(set) => set.rules.push(rule),
+                None => return Err(err("rule outside of a [rule set]".to_owned())),
+            }
+        }
+    }
+    Ok(sets)
+}
+
+/// Outcome of one rule for one string.
+struct Verdict<'a> {
+    rule: &'a Rule,
+    passed: bool,
+    positions: Vec<usize>,
+}
+
+impl RuleSet {
+    fn explain(&self, s: &str) -> Vec<Verdict<'_>> {
+        self.rules.iter().map(|rule| {
+            let mut m = Matcher::new(&rule.primitive);
+            let positions: Vec<usize> = s.chars().filter_map(|ch| m.feed(ch)).collect();
+            Verdict { rule, passed: rule.passes(positions.len()), positions }
+        }).collect()
+    }
+}
+
+/// Evaluates any number of rule sets in a single pass over the input: every
+/// character is fed once to the matchers of all rules of all sets.
+struct Engine<'a> {
+    sets: &'a [RuleSet],
+    matchers: Vec<Vec<(Matcher<'a>, usize)>>,
+    good: Vec<u32>,
+}
+
+impl<'a> Engine<'a> {
+    fn new(sets: &'a [RuleSet]) -> Engine<'a> {
+        let matchers = sets.iter()
+            .map(|set| set.rules.iter().map(|r| (Matcher::new(&r.primitive), 0)).collect())
+            .collect();
+        Engine { sets, matchers, good: vec![0; sets.len()] }
+    }
+
+    /// Checks one string against all rule sets.
+    fn feed_line(&mut self, line: &str) {
+        for ms in self.matchers.iter_mut() {
+            ms.iter_mut().for_each(|(m, count)| { m.reset(); *count = 0; });
+        }
+        for ch in line.chars() {
+            for (m, count) in self.matchers.iter_mut().flatten() {
+                if m.feed(ch).is_some() {
+                    *count += 1;
                 }
-            } else {
-                pairs.insert(key, idx);
             }
-            split_double |= &line[idx-1..idx] == &line[idx+1..idx+2];// && &line[idx..idx+1] != &line[idx+1..idx+2];
+        }
+        for (idx, set) in self.sets.iter().enumerate() {
+            if set.rules.iter().zip(&self.matchers[idx]).all(|(r, (_, count))| r.passes(*count)) {
+                self.good[idx] += 1;
+            }
+        }
+    }
 
+    /// Feeds every line of the reader. Lines that are not valid UTF-8 are
+    /// decoded lossily instead of aborting the run.
+    fn feed_reader<R: BufRead>(&mut self, mut reader: R) -> io::Result<()> {
+        let mut buf = Vec::new();
+        while reader.read_until(b'\n', &mut buf)? > 0 {
+            let line = String::from_utf8_lossy(&buf);
+            self.feed_line(line.trim_end_matches(['\n', '\r']));
+            buf.clear();
         }
-        if double_pair && split_double {
-            good_count += 1;
-            //println!("{} -> {}, {}", &line, double_pair, split_double);
-        };
-        println!("{} -> {:?}, {}, {}", &line, pairs, double_pair, split_double);
+        Ok(())
     }
-    println!("Good: {}", good_count);
 }
 
+impl fmt::Display for Verdict<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} (found {} at {:?})",
+               if self.passed { "pass" } else { "FAIL" }, self.rule.text, self.positions.len(), self.positions)
+    }
+}
 
+fn read_rules(filename: &str) -> Vec<RuleSet> {
+    let mut text = String::new();
+    File::open(filename).expect("no such file").read_to_string(&mut text).unwrap();
+    match parse_rule_sets(&text) {
+        Ok(sets) => sets,
+        Err(e) => panic!("{}: {}", filename, e),
+    }
+}
 
 
+/// Usage: day05 [--rules FILE] [--input FILE] [--explain]
+///
+/// Counts the nice strings for every rule set of the rule file (default
+/// `data/05/rules.txt` with both parts of the puzzle) in one pass over the
+/// input. `--explain` shows for each string which rules passed or failed
+/// and where they fired.
 fn main() {
-    //part_1();
-    part_2();
-}
\ No newline at end of file
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let option = |name: &str| args.iter().position(|a| a == name).and_then(|i| args.get(i + 1));
+    let rules = read_rules(option("--rules").map_or("data/05/rules.txt", String::as_str));
+    let input = option("--input").map_or("data/05/input.txt", String::as_str);
+
+    if args.iter().any(|a| a == "--explain") {
+        let file = File::open(input).expect("no such file");
+        for line in BufReader::new(file).lines().map_while(Result::ok) {
+            for set in rules.iter() {
+                let verdicts = set.explain(&line);
+                let nice = verdicts.iter().all(|v| v.passed);
+                println!("[{}] {}: {}", set.name, line, if nice { "nice" } else { "naughty" });
+                verdicts.iter().for_each(|v| println!("    {}", v));
+            }
+        }
+    }
+
+    let mut engine = Engine::new(&rules);
+    engine.feed_reader(BufReader::new(File::open(input).expect("no such file"))).unwrap();
+    for (set, good) in rules.iter().zip(engine.good) {
+        println!("[{}] Good: {}", set.name, good);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PUZZLE_RULES: &str = "[part 1]\ncount aeiou >= 3\ndouble\ncontains ab cd pq xy == 0\n\n[part 2]\nrepeated_pair\nsplit_double\n";
+
+    fn is_nice(set: &RuleSet, s: &str) -> bool {
+        set.explain(s).iter().all(|v| v.passed)
+    }
+
+    #[test]
+    fn test_puzzle_rules() {
+        let sets = parse_rule_sets(PUZZLE_RULES).unwrap();
+        assert!(is_nice(&sets[0], "ugknbfddgicrmopn"));
+        assert!(is_nice(&sets[0], "aaa"));
+        assert!(!is_nice(&sets[0], "jchzalrnumimnmhp"));
+        assert!(!is_nice(&sets[0], "haegwjzuvuyypxyu"));
+        assert!(!is_nice(&sets[0], "dvszwmarrgswjxmb"));
+        assert!(is_nice(&sets[1], "qjhvhtzxzqqjkmpb"));
+        assert!(is_nice(&sets[1], "xxyxx"));
+        assert!(!is_nice(&sets[1], "aaa"));
+        assert!(!is_nice(&sets[1], "uurcxstgmygtbstg"));
+        assert!(!is_nice(&sets[1], "ieodomkazucvgmuy"));
+    }
+
+    #[test]
+    fn test_explain() {
+        let sets = parse_rule_sets(PUZZLE_RULES).unwrap();
+        let v = sets[0].explain("haegwjzuvuyypxyu");
+        assert!(v[0].passed && v[1].passed && !v[2].passed);
+        assert_eq!(v[2].positions, vec![13]);
+        assert_eq!(parse_rule_sets("double"), Err(ParseError { line: 1, msg: "rule outside of a [rule set]".to_owned() }));
+        assert!(parse_rule_sets("[x]\ntriple").is_err());
+    }
+
+    #[test]
+    fn test_engine() {
+        let sets = parse_rule_sets(PUZZLE_RULES).unwrap();
+        let mut engine = Engine::new(&sets);
+        let mut input = "ugknbfddgicrmopn\r\nqjhvhtzxzqqjkmpb\n\na\nxxyxx\näöüßaeeiou\n".as_bytes().to_vec();
+        input.extend_from_slice(b"\xff\xfeaa");
+        engine.feed_reader(&input[..]).unwrap();
+        assert_eq!(engine.good, vec![2, 2]);
+        let v = sets[1].explain("aaaa");
+        assert_eq!(v[0].positions, vec![2]);
+        assert_eq!(v[1].positions, vec![0, 1]);
+    }
+
+    #[test]
+    fn test_repeated_pair_memory() {
+        let primitive = Primitive::RepeatedPair;
+        let mut m = Matcher::new(&primitive);
+        assert_eq!("xyaxy".chars().filter_map(|ch| m.feed(ch)).collect::<Vec<_>>(), vec![3]);
+        assert!(m.pairs.is_empty());
+        m.reset();
+        assert!(m.touched.is_empty() && m.ascii_pairs.iter().all(|&p| p == UNSEEN));
+        assert_eq!("xyäöxyäö".chars().filter_map(|ch| m.feed(ch)).collect::<Vec<_>>(), vec![4, 5, 6]);
+        assert_eq!(m.pairs.len(), 3);
+    }
+}
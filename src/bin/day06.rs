@@ -58,18 +58,24 @@
 //! turn on 0,0 through 0,0     -> would increase the total brightness by 1.
 //! toggle 0,0 through 999,999  -> would increase the total brightness by 2000000.
 
-use std::{fs::File, io::{BufReader, BufRead}};
+use std::{fs::File, io::{self, BufReader, BufRead, BufWriter, Write}};
 
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 enum Instruction {
     TurnOn,
     TurnOff,
     Toggle,
+    /// `set N`: set the brightness to N.
+    Set(u32),
+    /// `dim N%`: reduce the brightness by N percent.
+    Dim(u32),
+    /// `color #rrggbb`: set the colour.
+    Color(u8, u8, u8),
     Nop,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 struct Pos {
     x: u32,
     y: u32,
@@ -82,6 +88,28 @@ struct Action {
     end: Pos,
 }
 
+impl std::fmt::Display for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.action_type {
+            Instruction::TurnOn         => write!(f, "turn on")?,
+            Instruction::TurnOff        => write!(f, "turn off")?,
+            Instruction::Toggle         => write!(f, "toggle")?,
+            Instruction::Set(n)         => write!(f, "set {}", n)?,
+            Instruction::Dim(p)         => write!(f, "dim {}%", p)?,
+            Instruction::Color(r, g, b) => write!(f, "color #{:02x}{:02x}{:02x}", r, g, b)?,
+            Instruction::Nop            => return write!(f, "nop"),
+        }
+        write!(f, " {},{} through {},{}", self.start.x, self.start.y, self.end.x, self.end.y)
+    }
+}
+
+impl Action {
+    fn contains(&self, p: Pos) -> bool {
+        self.action_type != Instruction::Nop
+            && (self.start.x..=self.end.x).contains(&p.x) && (self.start.y..=self.end.y).contains(&p.y)
+    }
+}
+
 static NOP_ACTION: Action = Action { action_type: Instruction::Nop,
                                      start: Pos{ x: 0, y: 0 },
                                      end: Pos{ x:0, y:0 } };
@@ -95,7 +123,7 @@ fn parse_pos(s: &str) -> Pos {
 
 
 fn split_line(s: &str) -> Action {
-    let mut i = s.trim().split_whitespace();
+    let mut i = s.split_whitespace();
     match i.next().expect("Wrong line structure.") {
         "turn" => match i.next().expect("Wring inner line structure.") {
             "on"  => { let start = parse_pos(i.next().unwrap());
@@ -115,10 +143,154 @@ fn split_line(s: &str) -> Action {
                       let end = parse_pos(i.next().unwrap());
                       Action { action_type: Instruction::Toggle, start, end }
         },
+        verb @ ("set" | "dim" | "color") => {
+            let arg = i.next().expect("Missing argument.");
+            let action_type = parse_argument(verb, arg).unwrap_or_else(|| panic!("Bad argument {:?} for {}", arg, verb));
+            let start = parse_pos(i.next().unwrap());
+            i.next();
+            let end = parse_pos(i.next().unwrap());
+            Action { action_type, start, end }
+        },
         &_ =>       NOP_ACTION,
     }
 }
 
+fn parse_argument(verb: &str, arg: &str) -> Option<Instruction> {
+    match verb {
+        "set" => arg.parse().ok().map(Instruction::Set),
+        "dim" => arg.strip_suffix('%')?.parse().ok().filter(|&p| p <= 100).map(Instruction::Dim),
+        "color" => {
+            let hex = arg.strip_prefix('#').filter(|h| h.len() == 6)?;
+            let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+            Some(Instruction::Color(channel(0)?, channel(2)?, channel(4)?))
+        },
+        _ => None,
+    }
+}
+
+/// Meaning of the instructions for a single light.
+trait LightModel: Clone {
+    type State: Copy + Default;
+
+    fn name(&self) -> &'static str;
+
+    fn supports(&self, i: Instruction) -> bool;
+
+    fn apply(&self, state: Self::State, i: Instruction) -> Self::State;
+
+    fn is_lit(&self, state: &Self::State) -> bool;
+
+    /// Contribution of the light to the total level, e.g. its brightness.
+    fn level(&self, state: &Self::State) -> u64;
+}
+
+/// Part one: lights are either on or off.
+#[derive(Clone)]
+struct OnOff;
+
+impl LightModel for OnOff {
+    type State = bool;
+
+    fn name(&self) -> &'static str { "on/off" }
+
+    fn supports(&self, i: Instruction) -> bool {
+        matches!(i, Instruction::TurnOn | Instruction::TurnOff | Instruction::Toggle | Instruction::Nop)
+    }
+
+    fn apply(&self, state: bool, i: Instruction) -> bool {
+        match i {
+            Instruction::TurnOn  => true,
+            Instruction::TurnOff => false,
+            Instruction::Toggle  => !state,
+            _                    => state,
+        }
+    }
+
+    fn is_lit(&self, state: &bool) -> bool { *state }
+
+    fn level(&self, state: &bool) -> u64 { *state as u64 }
+}
+
+/// What happens when the brightness leaves `0..=cap`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Overflow {
+    Saturating,
+    Wrapping,
+}
+
+/// Part two: lights have a brightness. Without cap and saturating this is
+/// the puzzle's model.
+#[derive(Clone)]
+struct Brightness {
+    cap: u32,
+    overflow: Overflow,
+}
+
+impl Brightness {
+    fn adjust(&self, state: u32, delta: i64) -> u32 {
+        let v = state as i64 + delta;
+        match self.overflow {
+            Overflow::Saturating => v.clamp(0, self.cap as i64) as u32,
+            Overflow::Wrapping   => v.rem_euclid(self.cap as i64 + 1) as u32,
+        }
+    }
+}
+
+impl LightModel for Brightness {
+    type State = u32;
+
+    fn name(&self) -> &'static str { "brightness" }
+
+    fn supports(&self, i: Instruction) -> bool {
+        !matches!(i, Instruction::Color(..))
+    }
+
+    fn apply(&self, state: u32, i: Instruction) -> u32 {
+        match i {
+            Instruction::TurnOn  => self.adjust(state, 1),
+            Instruction::TurnOff => self.adjust(state, -1),
+            Instruction::Toggle  => self.adjust(state, 2),
+            Instruction::Set(n)  => self.adjust(0, n as i64),
+            Instruction::Dim(p)  => (state as u64 * (100 - p) as u64 / 100) as u32,
+            _                    => state,
+        }
+    }
+
+    fn is_lit(&self, state: &u32) -> bool { *state > 0 }
+
+    fn level(&self, state: &u32) -> u64 { *state as u64 }
+}
+
+/// Colour lights. `turn on` means white, `toggle` inverts the colour and
+/// `set N` gives a gray of N.
+#[derive(Clone)]
+struct Rgb;
+
+impl LightModel for Rgb {
+    type State = (u8, u8, u8);
+
+    fn name(&self) -> &'static str { "rgb" }
+
+    fn supports(&self, _: Instruction) -> bool { true }
+
+    fn apply(&self, (r, g, b): (u8, u8, u8), i: Instruction) -> (u8, u8, u8) {
+        let dim = |c: u8, p: u32| (c as u32 * (100 - p) / 100) as u8;
+        match i {
+            Instruction::TurnOn         => (255, 255, 255),
+            Instruction::TurnOff        => (0, 0, 0),
+            Instruction::Toggle         => (!r, !g, !b),
+            Instruction::Set(n)         => { let n = n.min(255) as u8; (n, n, n) },
+            Instruction::Dim(p)         => (dim(r, p), dim(g, p), dim(b, p)),
+            Instruction::Color(r, g, b) => (r, g, b),
+            Instruction::Nop            => (r, g, b),
+        }
+    }
+
+    fn is_lit(&self, state: &(u8, u8, u8)) -> bool { *state != (0, 0, 0) }
+
+    fn level(&self, &(r, g, b): &(u8, u8, u8)) -> u64 { r as u64 + g as u64 + b as u64 }
+}
+
 
 fn read_lines<P>(filename: P) -> Vec<Action>
 where P: AsRef<std::path::Path>, {
@@ -130,47 +302,514 @@ where P: AsRef<std::path::Path>, {
 
 }
 
-fn main (){
-    let mut grid = [[(false, 0u32); 1000]; 1000];
-    let actions = read_lines("data/06/input.txt");
-
-    for i in actions {
-        let lenx = i.end.x - i.start.x + 1;
-        let leny = i.end.y - i.start.y + 1;
-        println!{"x: {}, y: {}, dx: {}, dy: {}, do: {:?}", i.start.x, i.start.y, lenx, leny, i.action_type};
-        for dx in 0..lenx {
-            for dy in 0..leny {
-                let x = (i.start.x + dx) as usize;
-                let y = (i.start.y + dy) as usize;
-                match i.action_type {
-                    Instruction::TurnOn  => {
-                        grid[x][y].0 = true;
-                        grid[x][y].1 += 1;
-                    },
-                    Instruction::TurnOff => {
-                        grid[x][y].0 = false;
-                        if grid[x][y].1 > 0 {
-                            grid[x][y].1 -= 1;
-                        }
-                    },
-                    Instruction::Toggle  => {
-                        grid[x][y].0 = !grid[x][y].0;
-                        grid[x][y].1 += 2;
-                    },
-                    Instruction::Nop     => (),
+/// Light grid over compressed coordinates. The rectangle borders of all
+/// actions cut the grid into blocks; all lights of a block always share the
+/// same state, so each block is stored once together with its area. Lit
+/// count and level are computed per block instead of per light.
+struct CompressedGrid<M: LightModel> {
+    model: M,
+    /// Block borders along x; block `i` covers `xs[i]..xs[i + 1]`.
+    xs: Vec<u32>,
+    /// Block borders along y.
+    ys: Vec<u32>,
+    /// State of each block, row by row.
+    blocks: Vec<M::State>,
+}
+
+impl<M: LightModel> CompressedGrid<M> {
+    fn new(model: M, actions: &[Action], width: u32, height: u32) -> CompressedGrid<M> {
+        fn borders(mut v: Vec<u32>, size: u32) -> Vec<u32> {
+            v.retain(|&c| c < size);
+            v.extend([0, size]);
+            v.sort_unstable();
+            v.dedup();
+            v
+        }
+        let xs = borders(actions.iter().flat_map(|a| [a.start.x, a.end.x + 1]).collect(), width);
+        let ys = borders(actions.iter().flat_map(|a| [a.start.y, a.end.y + 1]).collect(), height);
+        let blocks = vec![M::State::default(); (xs.len() - 1) * (ys.len() - 1)];
+        CompressedGrid { model, xs, ys, blocks }
+    }
+
+    /// Range of blocks covering `start..=end`.
+    fn block_range(borders: &[u32], start: u32, end: u32) -> std::ops::Range<usize> {
+        let first = borders.partition_point(|&b| b <= start) - 1;
+        let last = borders.partition_point(|&b| b <= end);
+        first..last
+    }
+
+    fn apply(&mut self, a: &Action) {
+        let nx = self.xs.len() - 1;
+        for j in Self::block_range(&self.ys, a.start.y, a.end.y) {
+            for i in Self::block_range(&self.xs, a.start.x, a.end.x) {
+                let block = &mut self.blocks[j * nx + i];
+                *block = self.model.apply(*block, a.action_type);
+            }
+        }
+    }
+
+    /// Blocks overlapping the rectangle `start..=end` with the number of
+    /// their lights inside the rectangle.
+    fn blocks_in(&self, start: Pos, end: Pos) -> impl Iterator<Item = (&M::State, u64)> {
+        let nx = self.xs.len() - 1;
+        let overlap = |borders: &[u32], i: usize, from: u32, to: u32| {
+            (borders[i + 1].min(to + 1) - borders[i].max(from)) as u64
+        };
+        Self::block_range(&self.ys, start.y, end.y).flat_map(move |j| {
+            Self::block_range(&self.xs, start.x, end.x).map(move |i| {
+                let area = overlap(&self.xs, i, start.x, end.x) * overlap(&self.ys, j, start.y, end.y);
+                (&self.blocks[j * nx + i], area)
+            })
+        })
+    }
+
+    fn whole(&self) -> (Pos, Pos) {
+        (Pos { x: 0, y: 0 }, Pos { x: self.xs.last().unwrap() - 1, y: self.ys.last().unwrap() - 1 })
+    }
+
+    fn count_in(&self, start: Pos, end: Pos) -> u64 {
+        self.blocks_in(start, end).filter(|(b, _)| self.model.is_lit(b)).map(|(_, area)| area).sum()
+    }
+
+    fn level_in(&self, start: Pos, end: Pos) -> u64 {
+        self.blocks_in(start, end).map(|(b, area)| self.model.level(b) * area).sum()
+    }
+
+    fn count(&self) -> u64 {
+        let (start, end) = self.whole();
+        self.count_in(start, end)
+    }
+
+    fn level(&self) -> u64 {
+        let (start, end) = self.whole();
+        self.level_in(start, end)
+    }
+
+    fn max_level(&self) -> u64 {
+        self.blocks.iter().map(|b| self.model.level(b)).max().unwrap_or(0)
+    }
+
+    /// Writes the image row by row. All rows of a block row are equal, so
+    /// each is built once from the blocks and then repeated.
+    fn write_rows<W: Write, F>(&self, out: &mut W, pixel_bytes: usize, mut pixel: F) -> io::Result<()>
+    where F: FnMut(&M::State, &mut Vec<u8>), {
+        let nx = self.xs.len() - 1;
+        let mut row = Vec::new();
+        for j in 0..self.ys.len() - 1 {
+            row.clear();
+            for i in 0..nx {
+                let mut px = Vec::with_capacity(pixel_bytes);
+                pixel(&self.blocks[j * nx + i], &mut px);
+                for _ in self.xs[i]..self.xs[i + 1] {
+                    row.extend_from_slice(&px);
                 }
             }
+            for _ in self.ys[j]..self.ys[j + 1] {
+                out.write_all(&row)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Binary PBM (P4), lit lights are white.
+    fn write_pbm<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let (width, height) = (*self.xs.last().unwrap(), *self.ys.last().unwrap());
+        write!(out, "P4\n{} {}\n", width, height)?;
+        // collect the lights as bytes first, PBM packs eight lights per byte
+        let mut bits = Vec::new();
+        self.write_rows(&mut bits, 1, |b, px| px.push(!self.model.is_lit(b) as u8))?;
+        for row in bits.chunks(width as usize) {
+            let packed: Vec<u8> = row.chunks(8)
+                .map(|c| c.iter().enumerate().fold(0u8, |acc, (k, &bit)| acc | (bit << (7 - k))))
+                .collect();
+            out.write_all(&packed)?;
         }
+        Ok(())
     }
-    let mut count = 0u32;
-    let mut brightnes = 0u32;
-    for x in 0..1000 {
-        for y in 0..1000 {
-            brightnes += grid[x as usize][y as usize].1;
-            if grid[x as usize][y as usize].0 == true {
-                count += 1;
+
+    /// Binary PGM (P5) of the levels, scaled to `max_level`; above 255 two
+    /// bytes per light are written.
+    fn write_pgm<W: Write>(&self, out: &mut W, max_level: u64) -> io::Result<()> {
+        let (width, height) = (*self.xs.last().unwrap(), *self.ys.last().unwrap());
+        let maxval = max_level.clamp(1, 65535);
+        write!(out, "P5\n{} {}\n{}\n", width, height, maxval)?;
+        self.write_rows(out, if maxval > 255 { 2 } else { 1 }, |b, px| {
+            let v = self.model.level(b).min(max_level) * maxval / max_level.max(1);
+            if maxval > 255 {
+                px.extend_from_slice(&(v as u16).to_be_bytes());
+            } else {
+                px.push(v as u8);
             }
+        })
+    }
+
+    fn export(&self, format: Format, filename: &str, max_level: u64) -> io::Result<()> {
+        let (width, height) = (*self.xs.last().unwrap() as u64, *self.ys.last().unwrap() as u64);
+        if width * height > MAX_IMAGE_PIXELS {
+            return Err(io::Error::other(format!("{}x{} grid is too large for an image", width, height)));
         }
-    } println!("count: {}, brightnes: {} ", count, brightnes);
+        let mut out = BufWriter::new(File::create(filename)?);
+        match format {
+            Format::Pbm => self.write_pbm(&mut out),
+            Format::Pgm => self.write_pgm(&mut out, max_level),
+        }
+    }
+}
+
+/// A question about the grid; `after` counts the instructions that have
+/// been run (all if missing, 0 is the dark grid).
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Query {
+    /// `lit X,Y through X,Y [after K]`: lit lights and level in a rectangle.
+    Region { start: Pos, end: Pos, after: Option<usize> },
+    /// `cell X,Y [after K]`: state of a single light.
+    Cell { pos: Pos, after: Option<usize> },
+    /// `touched X,Y`: all instructions that changed the light.
+    Touched { pos: Pos },
 }
 
+impl Query {
+    fn parse(s: &str) -> Result<Query, String> {
+        let pos = |w: &str| -> Result<Pos, String> {
+            let (x, y) = w.split_once(',').ok_or(format!("{:?} is no position", w))?;
+            match (x.parse(), y.parse()) {
+                (Ok(x), Ok(y)) => Ok(Pos { x, y }),
+                _ => Err(format!("{:?} is no position", w)),
+            }
+        };
+        let after = |w: &[&str]| -> Result<Option<usize>, String> {
+            match w {
+                [] => Ok(None),
+                ["after", k] => k.parse().map(Some).map_err(|_| format!("{:?} is no instruction number", k)),
+                _ => Err(format!("unexpected {:?}", w.join(" "))),
+            }
+        };
+        let words: Vec<&str> = s.split_whitespace().collect();
+        match words.as_slice() {
+            ["lit", a, "through", b, rest @ ..] => {
+                let (start, end) = (pos(a)?, pos(b)?);
+                if start.x > end.x || start.y > end.y {
+                    return Err(format!("{} through {} is reversed", a, b));
+                }
+                Ok(Query::Region { start, end, after: after(rest)? })
+            },
+            ["cell", p, rest @ ..] => Ok(Query::Cell { pos: pos(p)?, after: after(rest)? }),
+            ["touched", p] => Ok(Query::Touched { pos: pos(p)? }),
+            _ => Err(format!("unknown query {:?}", s)),
+        }
+    }
+}
+
+/// The instruction history of a grid, to look at any light after any
+/// instruction.
+struct Timeline<'a, M: LightModel> {
+    model: M,
+    actions: &'a [Action],
+    size: u32,
+}
+
+impl<M: LightModel> Timeline<'_, M>
+where M::State: std::fmt::Debug, {
+    fn grid_after(&self, k: usize) -> CompressedGrid<M> {
+        let actions = &self.actions[..k.min(self.actions.len())];
+        let mut grid = CompressedGrid::new(self.model.clone(), actions, self.size, self.size);
+        actions.iter().for_each(|a| grid.apply(a));
+        grid
+    }
+
+    /// Numbers (starting at 1) of the instructions covering the light.
+    fn touched(&self, pos: Pos) -> Vec<usize> {
+        self.actions.iter().enumerate().filter(|(_, a)| a.contains(pos)).map(|(k, _)| k + 1).collect()
+    }
+
+    fn cell_after(&self, pos: Pos, k: usize) -> M::State {
+        self.actions.iter().take(k).filter(|a| a.contains(pos))
+            .fold(M::State::default(), |state, a| self.model.apply(state, a.action_type))
+    }
+
+    fn answer(&self, query: &Query) -> String {
+        let all = self.actions.len();
+        let (Query::Region { start: pos, .. } | Query::Cell { pos, .. } | Query::Touched { pos }) = *query;
+        if pos.x >= self.size || pos.y >= self.size {
+            return format!("outside of the {0}x{0} grid", self.size);
+        }
+        match *query {
+            Query::Region { start, end, after } => {
+                // the part of the rectangle on the grid
+                let end = Pos { x: end.x.min(self.size - 1), y: end.y.min(self.size - 1) };
+                let grid = self.grid_after(after.unwrap_or(all));
+                format!("lit: {}, level: {}", grid.count_in(start, end), grid.level_in(start, end))
+            },
+            Query::Cell { pos, after } => {
+                let state = self.cell_after(pos, after.unwrap_or(all));
+                format!("{:?}, level: {}", state, self.model.level(&state))
+            },
+            Query::Touched { pos } => {
+                let mut state = M::State::default();
+                let lines: Vec<String> = self.touched(pos).iter().map(|&k| {
+                    state = self.model.apply(state, self.actions[k - 1].action_type);
+                    format!("\n  {:4}: {:40} -> {:?}", k, self.actions[k - 1].to_string(), state)
+                }).collect();
+                format!("{} instructions{}", lines.len(), lines.concat())
+            },
+        }
+    }
+}
+
+/// Largest grid that is exported as image.
+const MAX_IMAGE_PIXELS: u64 = 100_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    /// On/off as PBM.
+    Pbm,
+    /// Level as PGM.
+    Pgm,
+}
+
+impl Format {
+    fn extension(&self) -> &'static str {
+        match self {
+            Format::Pbm => "pbm",
+            Format::Pgm => "pgm",
+        }
+    }
+}
+
+/// Images of the final grid and numbered frames after every instruction,
+/// e.g. `frames/step` gives `frames/step0001.pbm`, `frames/step0002.pbm`...
+#[derive(Default)]
+struct Exports {
+    images: Vec<(Format, String)>,
+    frames: Vec<(Format, String)>,
+}
+
+impl Exports {
+    fn only(&self, format: Format) -> Exports {
+        let keep = |v: &Vec<(Format, String)>| v.iter().filter(|e| e.0 == format).cloned().collect();
+        Exports { images: keep(&self.images), frames: keep(&self.frames) }
+    }
+}
+
+fn run<M: LightModel>(model: M, actions: &[Action], size: u32, exports: &Exports) -> io::Result<CompressedGrid<M>> {
+    if let Some(a) = actions.iter().find(|a| !model.supports(a.action_type)) {
+        panic!("The {} model does not support {:?}", model.name(), a.action_type);
+    }
+    // frames share one gray scale, so a dry run looks for the highest level
+    let mut max_level = 0;
+    if !exports.frames.is_empty() {
+        let mut grid = CompressedGrid::new(model.clone(), actions, size, size);
+        for a in actions {
+            grid.apply(a);
+            max_level = max_level.max(grid.max_level());
+        }
+    }
+    let mut grid = CompressedGrid::new(model, actions, size, size);
+    for (k, a) in actions.iter().enumerate() {
+        grid.apply(a);
+        for (format, prefix) in exports.frames.iter() {
+            grid.export(*format, &format!("{}{:04}.{}", prefix, k + 1, format.extension()), max_level)?;
+        }
+    }
+    for (format, filename) in exports.images.iter() {
+        grid.export(*format, filename, grid.max_level())?;
+    }
+    Ok(grid)
+}
+
+fn print_totals<M: LightModel>(grid: &CompressedGrid<M>) {
+    println!("lit: {}, level: {}", grid.count(), grid.level());
+}
+
+fn print_answers<M: LightModel>(model: M, actions: &[Action], size: u32, queries: &[(&String, Query)])
+where M::State: std::fmt::Debug, {
+    let timeline = Timeline { model, actions, size };
+    for (text, q) in queries {
+        println!("[{}] {}: {}", timeline.model.name(), text, timeline.answer(q));
+    }
+}
+
+/// Usage: day06 [--input FILE] [--size N] [--model onoff|brightness|rgb]
+///              [--cap N] [--wrapping] [--pbm FILE] [--pgm FILE]
+///              [--frames PREFIX] [--query QUERY]...
+///
+/// Runs the instructions on a grid of N x N lights (default 1000). Without
+/// a model both parts of the puzzle are solved. `--cap` and `--wrapping`
+/// set the range and overflow of the brightness model. The final grid is
+/// exported as PBM (on/off) and PGM (level), `--frames` writes both after
+/// every instruction. Without a model the PBM images show part one and the
+/// PGM images part two. Queries (see `Query`) are answered by both puzzle
+/// models, or by the chosen one.
+fn main (){
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let option = |name: &str| args.iter().position(|a| a == name).and_then(|i| args.get(i + 1));
+    let size: u32 = option("--size").map_or(1000, |s| s.parse().expect("grid size"));
+    let actions = read_lines(option("--input").map_or("data/06/input.txt", String::as_str));
+
+    for a in actions.iter() {
+        if a.start.x > a.end.x || a.start.y > a.end.y || a.end.x >= size || a.end.y >= size {
+            panic!("Rectangle {:?} does not fit into the {}x{} grid", a, size, size);
+        }
+    }
+    let brightness = Brightness {
+        cap: option("--cap").map_or(u32::MAX, |c| c.parse().expect("brightness cap")),
+        overflow: if args.iter().any(|a| a == "--wrapping") { Overflow::Wrapping } else { Overflow::Saturating },
+    };
+    let mut exports = Exports::default();
+    for (idx, arg) in args.iter().enumerate() {
+        let value = || args.get(idx + 1).expect("missing file name").clone();
+        match arg.as_str() {
+            "--pbm" => exports.images.push((Format::Pbm, value())),
+            "--pgm" => exports.images.push((Format::Pgm, value())),
+            "--frames" => exports.frames.extend([(Format::Pbm, value()), (Format::Pgm, value())]),
+            _ => (),
+        }
+    }
+    let queries: Vec<(&String, Query)> = args.windows(2).filter(|w| w[0] == "--query")
+        .map(|w| (&w[1], Query::parse(&w[1]).unwrap_or_else(|e| panic!("{}", e))))
+        .collect();
+    match option("--model").map(String::as_str) {
+        None => {
+            print_answers(OnOff, &actions, size, &queries);
+            print_answers(brightness.clone(), &actions, size, &queries);
+        },
+        Some("onoff") => print_answers(OnOff, &actions, size, &queries),
+        Some("brightness") => print_answers(brightness.clone(), &actions, size, &queries),
+        Some("rgb") => print_answers(Rgb, &actions, size, &queries),
+        Some(_) => (),
+    }
+    let result = match option("--model").map(String::as_str) {
+        None => run(OnOff, &actions, size, &exports.only(Format::Pbm)).and_then(|on_off| {
+            let brightness = run(brightness, &actions, size, &exports.only(Format::Pgm))?;
+            println!("count: {}, brightnes: {} ", on_off.count(), brightness.level());
+            Ok(())
+        }),
+        Some("onoff") => run(OnOff, &actions, size, &exports).map(|g| print_totals(&g)),
+        Some("brightness") => run(brightness, &actions, size, &exports).map(|g| print_totals(&g)),
+        Some("rgb") => run(Rgb, &actions, size, &exports).map(|g| print_totals(&g)),
+        Some(m) => panic!("Unknown model {}", m),
+    };
+    if let Err(e) = result {
+        eprintln!("Export failed: {}", e);
+        std::process::exit(1);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PUZZLE_BRIGHTNESS: Brightness = Brightness { cap: u32::MAX, overflow: Overflow::Saturating };
+
+    fn both(lines: &[&str], size: u32) -> (u64, u64) {
+        let actions: Vec<Action> = lines.iter().map(|l| split_line(l)).collect();
+        (run(OnOff, &actions, size, &Exports::default()).unwrap().count(), run(PUZZLE_BRIGHTNESS, &actions, size, &Exports::default()).unwrap().level())
+    }
+
+    fn level<M: LightModel>(model: M, lines: &[&str]) -> u64 {
+        let actions: Vec<Action> = lines.iter().map(|l| split_line(l)).collect();
+        run(model, &actions, 10, &Exports::default()).unwrap().level()
+    }
+
+    #[test]
+    fn test_models() {
+        let capped = || Brightness { cap: 3, overflow: Overflow::Saturating };
+        let wrapping = || Brightness { cap: 3, overflow: Overflow::Wrapping };
+        let lines = ["toggle 0,0 through 0,0", "turn on 0,0 through 0,0"];
+        assert_eq!(level(capped(), &lines), 3);
+        assert_eq!(level(wrapping(), &lines), 3);
+        assert_eq!(level(wrapping(), &["toggle 0,0 through 0,0", "toggle 0,0 through 0,0"]), 0);
+        assert_eq!(level(PUZZLE_BRIGHTNESS, &["set 50 0,0 through 0,1", "dim 50% 0,0 through 0,0"]), 75);
+        assert_eq!(level(Rgb, &["color #ff0000 0,0 through 1,0", "toggle 1,0 through 1,0"]), 255 + 510);
+        assert_eq!(parse_argument("color", "#12345g"), None);
+        assert_eq!(parse_argument("dim", "150%"), None);
+    }
+
+    #[test]
+    fn test_timeline() {
+        let actions: Vec<Action> = ["turn on 0,0 through 9,9", "toggle 5,5 through 9,9", "turn off 0,0 through 0,0"]
+            .iter().map(|l| split_line(l)).collect();
+        let on_off = Timeline { model: OnOff, actions: &actions, size: 10 };
+        let brightness = Timeline { model: PUZZLE_BRIGHTNESS, actions: &actions, size: 10 };
+        assert_eq!(on_off.grid_after(3).count_in(Pos { x: 4, y: 4 }, Pos { x: 5, y: 5 }), 3);
+        assert_eq!(on_off.grid_after(1).count_in(Pos { x: 4, y: 4 }, Pos { x: 5, y: 5 }), 4);
+        assert_eq!(brightness.grid_after(2).level_in(Pos { x: 4, y: 4 }, Pos { x: 5, y: 5 }), 6);
+        assert_eq!(brightness.cell_after(Pos { x: 7, y: 7 }, 2), 3);
+        assert_eq!(brightness.cell_after(Pos { x: 7, y: 7 }, 0), 0);
+        assert!(!on_off.cell_after(Pos { x: 7, y: 7 }, 3));
+        assert_eq!(on_off.touched(Pos { x: 0, y: 0 }), vec![1, 3]);
+        assert_eq!(Query::parse("lit 1,2 through 3,4 after 7"),
+                   Ok(Query::Region { start: Pos { x: 1, y: 2 }, end: Pos { x: 3, y: 4 }, after: Some(7) }));
+        assert!(Query::parse("cell 1;2").is_err());
+        assert!(Query::parse("lit 5,5 through 1,9").is_err());
+        let answer = |s: &str| on_off.answer(&Query::parse(s).unwrap());
+        assert_eq!(answer("lit 8,8 through 12,12 after 1"), "lit: 4, level: 4");
+        assert_eq!(answer("lit 10,0 through 12,12"), "outside of the 10x10 grid");
+        assert_eq!(answer("cell 9,9"), "false, level: 0");
+        assert_eq!(answer("cell 3,10"), "outside of the 10x10 grid");
+        assert_eq!(answer("touched 10,3"), "outside of the 10x10 grid");
+        assert_eq!(actions[1].to_string(), "toggle 5,5 through 9,9");
+    }
+
+    #[test]
+    fn test_compressed_grid() {
+        assert_eq!(both(&["turn on 0,0 through 999,999"], 1000), (1000000, 1000000));
+        assert_eq!(both(&["toggle 0,0 through 999,0"], 1000), (1000, 2000));
+        assert_eq!(both(&["turn on 0,0 through 999,999", "turn off 499,499 through 500,500"], 1000), (999996, 999996));
+        assert_eq!(both(&["turn off 0,0 through 9,9", "toggle 2,2 through 4,6", "turn on 3,0 through 3,9"], 10), (20, 40));
+        assert_eq!(both(&["toggle 0,0 through 999999,999999"], 1000000), (1000000000000, 2000000000000));
+    }
+
+    fn grid<M: LightModel>(model: M, lines: &[&str], size: u32) -> CompressedGrid<M> {
+        let actions: Vec<Action> = lines.iter().map(|l| split_line(l)).collect();
+        run(model, &actions, size, &Exports::default()).unwrap()
+    }
+
+    #[test]
+    fn test_pbm() {
+        let g = grid(OnOff, &["turn on 0,0 through 8,0"], 10);
+        let mut out = Vec::new();
+        g.write_pbm(&mut out).unwrap();
+        let header = b"P4\n10 10\n";
+        assert_eq!(&out[..header.len()], header);
+        // ten lights make two bytes per row, lit is white (0), padding is 0
+        let pixels = &out[header.len()..];
+        assert_eq!(pixels.len(), 20);
+        assert_eq!(pixels[..2], [0x00, 0x40]);
+        assert!(pixels[2..].chunks(2).all(|row| row == [0xff, 0xc0]));
+    }
+
+    #[test]
+    fn test_pgm() {
+        let g = grid(PUZZLE_BRIGHTNESS, &["set 100 0,0 through 0,0", "set 50 1,0 through 1,0"], 2);
+        let mut out = Vec::new();
+        g.write_pgm(&mut out, g.max_level()).unwrap();
+        assert_eq!(out, b"P5\n2 2\n100\n\x64\x32\x00\x00");
+
+        let g = grid(PUZZLE_BRIGHTNESS, &["set 300 0,0 through 0,0", "set 1 1,1 through 1,1"], 2);
+        let mut out = Vec::new();
+        g.write_pgm(&mut out, g.max_level()).unwrap();
+        assert_eq!(out, b"P5\n2 2\n300\n\x01\x2c\x00\x00\x00\x00\x00\x01");
+        // a fixed scale for frames
+        let mut out = Vec::new();
+        g.write_pgm(&mut out, 600).unwrap();
+        assert_eq!(out[out.len() - 8..], [0x01, 0x2c, 0, 0, 0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn test_frames() {
+        let dir = std::env::temp_dir().join(format!("day06-frames-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let prefix = dir.join("step").to_str().unwrap().to_owned();
+        let exports = Exports { images: vec![(Format::Pgm, format!("{}final.pgm", prefix))], frames: vec![(Format::Pbm, prefix.clone())] };
+        let actions: Vec<Action> = ["turn on 0,0 through 1,1", "toggle 0,0 through 0,0"].iter().map(|l| split_line(l)).collect();
+        run(OnOff, &actions, 4, &exports).unwrap();
+        let mut names: Vec<String> = std::fs::read_dir(&dir).unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap()).collect();
+        names.sort();
+        assert_eq!(names, ["step0001.pbm", "step0002.pbm", "stepfinal.pgm"]);
+        assert_eq!(std::fs::read(dir.join("step0002.pbm")).unwrap(), b"P4\n4 4\n\xb0\x30\xf0\xf0");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -68,11 +68,42 @@
 
 
 
-use std::borrow::{Borrow, BorrowMut};
 use std::fs::File;
-use std::hash::Hash;
-use std::io::{BufReader, BufRead};
-use std::collections::HashMap;
+use std::io::{self, BufReader, BufRead, BufWriter, Write};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+
+/// Width of all wires of a circuit. The puzzle uses 16 bit, every gate
+/// wraps its result to the bus width.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Bus {
+    width: u32,
+}
+
+impl Bus {
+    const PUZZLE: Bus = Bus { width: 16 };
+
+    fn new(width: u32) -> Result<Bus, String> {
+        if (1..=64).contains(&width) {
+            Ok(Bus { width })
+        } else {
+            Err(format!("bus width {} is not within 1..=64", width))
+        }
+    }
+
+    fn mask(self) -> u64 {
+        u64::MAX >> (64 - self.width)
+    }
+
+    fn signal(self, v: u64) -> Signal {
+        Signal(v & self.mask())
+    }
+}
+
+/// Value on a wire, always within the width of its bus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct Signal(u64);
 
 
 #[derive(Debug, Clone, PartialEq)]
@@ -83,23 +114,50 @@ enum Operator {
     Not,
     RShift,
     LShift,
+    Xor,
+    Nand,
+    Nor,
+    /// Rotation within the bus width.
+    RotL,
+    RotR,
+}
+
+fn operator_name(op: &Operator) -> &'static str {
+    match op {
+        Operator::Assign => "",
+        Operator::And    => "AND",
+        Operator::Or     => "OR",
+        Operator::Not    => "NOT",
+        Operator::RShift => "RSHIFT",
+        Operator::LShift => "LSHIFT",
+        Operator::Xor    => "XOR",
+        Operator::Nand   => "NAND",
+        Operator::Nor    => "NOR",
+        Operator::RotL   => "ROTL",
+        Operator::RotR   => "ROTR",
+    }
 }
 
-fn into_operator(s: &str) -> Operator {
+fn into_operator(s: &str) -> Result<Operator, String> {
     match s {
-        "AND"    => Operator::And,
-        "OR"     => Operator::Or,
-        "NOT"    => Operator::Not,
-        "RSHIFT" => Operator::RShift,
-        "LSHIFT" => Operator::LShift,
-        &_       => Operator::Assign,
+        "AND"    => Ok(Operator::And),
+        "OR"     => Ok(Operator::Or),
+        "NOT"    => Ok(Operator::Not),
+        "RSHIFT" => Ok(Operator::RShift),
+        "LSHIFT" => Ok(Operator::LShift),
+        "XOR"    => Ok(Operator::Xor),
+        "NAND"   => Ok(Operator::Nand),
+        "NOR"    => Ok(Operator::Nor),
+        "ROTL"   => Ok(Operator::RotL),
+        "ROTR"   => Ok(Operator::RotR),
+        &_       => Err(format!("unknown operator {:?}", s)),
     }
 }
 
 #[derive(Debug, Clone)]
 enum Operand {
     Label(String),
-    Number(i32),
+    Number(u64),
 }
 
 #[derive(Debug, Clone)]
@@ -110,50 +168,73 @@ struct Instruction {
     orb: Option<Operand>,
 }
 
+impl std::fmt::Display for Operand {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Operand::Label(l) => write!(f, "{}", l),
+            Operand::Number(n) => write!(f, "{}", n),
+        }
+    }
+}
+
+/// Writes the instruction in the booklet format, e.g. `x AND y -> z`.
+impl std::fmt::Display for Instruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match (&self.op, &self.orb) {
+            (Operator::Assign, _) => write!(f, "{} -> {}", self.ora, self.lbl),
+            (op, None) => write!(f, "{} {} -> {}", operator_name(op), self.ora, self.lbl),
+            (op, Some(orb)) => write!(f, "{} {} {} -> {}", self.ora, operator_name(op), orb, self.lbl),
+        }
+    }
+}
+
+fn into_operand(s: &str) -> Operand {
+    if let Ok(v) = s.parse::<u64>() {
+        Operand::Number(v)
+    } else {
+        Operand::Label(s.to_owned())
+    }
+}
+
 impl Instruction {
-    fn new(s: &str, l: &str) -> Instruction {
+    fn new(s: &str, l: &str) -> Result<Instruction, String> {
         let ora: Operand;
         let orb: Option<Operand>;
         let op: Operator;
-        let mut e = s.trim().split_whitespace();
-        let a = e.next().unwrap();
-        if let Some(b) = e.next() {
-            if let Some(c) = e.next() {
-                op = into_operator(b);
-                // three space-separeted strings <Operand> <Operator> <Operanad>
-                if let Ok(v) = a.parse::<i32>() {
-                    ora = Operand::Number(v);
-                } else {
-                    ora = Operand::Label(a.to_owned());
+        let words: Vec<&str> = s.split_whitespace().collect();
+        match words[..] {
+            // three space-separeted strings <Operand> <Operator> <Operanad>
+            [a, b, c] => {
+                op = into_operator(b)?;
+                if op == Operator::Not {
+                    return Err(format!("{} takes a single operand", b));
                 }
-                if let Ok(v) = c.parse::<i32>() {
-                    orb = Some(Operand::Number(v));
-                } else {
-                    orb = Some(Operand::Label(c.to_owned()));
+                ora = into_operand(a);
+                orb = Some(into_operand(c));
+            },
+            // two space-separated strings <Operator> <Operand>
+            [a, b] => {
+                op = into_operator(a)?;
+                if op != Operator::Not {
+                    return Err(format!("{} needs two operands", a));
                 }
-            } else {
-                // two space-separated strings <Operator> <Operand>
-                op = into_operator(a);
+                ora = into_operand(b);
                 orb = None;
-                if let Ok(v) = b.parse::<i32>() {
-                    ora = Operand::Number(v);
-                } else {
-                    ora = Operand::Label(b.to_owned());
-                }
-            }
-        } else {
+            },
             // only one space-separated string means a label or an scalar
-            op = Operator::Assign;
-            orb = None;
-            if let Ok(v) = a.parse::<i32>() {
-                ora = Operand::Number(v);
-            } else{
-                ora = Operand::Label(a.to_owned());
-            }
+            [a] => {
+                op = Operator::Assign;
+                ora = into_operand(a);
+                orb = None;
+            },
+            _ => return Err(format!("cannot parse {:?}", s)),
+        }
+        if l.is_empty() || l.contains(char::is_whitespace) {
+            return Err(format!("{:?} is no wire name", l));
         }
-        Instruction{lbl: l.to_owned(), op, ora, orb}
+        Ok(Instruction{lbl: l.to_owned(), op, ora, orb})
     }
-    
+
 }
 
 
@@ -164,101 +245,404 @@ struct Command {
 }
 
 impl Command {
-    fn new(line: &str) -> Command {
-        let mut l = line.trim().split("->");
-        Command {
-            lhs: l.next().unwrap().trim().to_owned(),
-            rhs: l.next().unwrap().trim().to_owned() 
+    fn new(line: &str) -> Result<Command, String> {
+        let (lhs, rhs) = line.trim().split_once("->").ok_or_else(|| format!("{:?} has no ->", line))?;
+        Ok(Command {
+            lhs: lhs.trim().to_owned(),
+            rhs: rhs.trim().to_owned()
+        })
+    }
+}
+
+
+fn do_logical_and(bus: Bus, a: u64, b: u64) -> Signal {
+    bus.signal(a & b)
+}
+
+fn do_logical_or(bus: Bus, a: u64, b: u64) -> Signal {
+    bus.signal(a | b)
+}
+
+fn do_logical_not(bus: Bus, a: u64) -> Signal {
+    bus.signal(!a)
+}
+
+/// Shifts by the bus width or more push out all bits.
+fn do_logical_lshift(bus: Bus, a: u64, b: u64) -> Signal {
+    if b < bus.width as u64 { bus.signal(bus.signal(a).0 << b) } else { Signal(0) }
+}
+
+fn do_logical_rshift(bus: Bus, a: u64, b: u64) -> Signal {
+    if b < bus.width as u64 { Signal(bus.signal(a).0 >> b) } else { Signal(0) }
+}
+
+fn do_logical_xor(bus: Bus, a: u64, b: u64) -> Signal {
+    bus.signal(a ^ b)
+}
+
+fn do_logical_nand(bus: Bus, a: u64, b: u64) -> Signal {
+    bus.signal(!(a & b))
+}
+
+fn do_logical_nor(bus: Bus, a: u64, b: u64) -> Signal {
+    bus.signal(!(a | b))
+}
+
+/// Rotates left within the bus width, by any amount.
+fn do_logical_rotl(bus: Bus, a: u64, b: u64) -> Signal {
+    let (a, r) = (bus.signal(a).0, (b % bus.width as u64) as u32);
+    if r == 0 { Signal(a) } else { bus.signal((a << r) | (a >> (bus.width - r))) }
+}
+
+fn do_logical_rotr(bus: Bus, a: u64, b: u64) -> Signal {
+    let r = b % bus.width as u64;
+    do_logical_rotl(bus, a, (bus.width as u64 - r) % bus.width as u64)
+}
+
+
+/// Input of a compiled gate.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Input {
+    Wire(usize),
+    Const(u64),
+}
+
+/// A gate driving one wire, with its inputs resolved to wire ids.
+#[derive(Debug, Clone, PartialEq)]
+struct Gate {
+    op: Operator,
+    a: Input,
+    b: Option<Input>,
+}
+
+impl Gate {
+    fn inputs(&self) -> impl Iterator<Item = usize> + '_ {
+        [Some(self.a), self.b].into_iter().flatten().filter_map(|i| match i {
+            Input::Wire(w) => Some(w),
+            Input::Const(_) => None,
+        })
+    }
+
+    fn eval(&self, bus: Bus, values: &[Signal]) -> Signal {
+        let get = |i: Input| match i {
+            Input::Wire(w) => values[w].0,
+            Input::Const(c) => c,
+        };
+        let (a, b) = (get(self.a), self.b.map_or(0, get));
+        match self.op {
+            Operator::Assign => bus.signal(a),
+            Operator::Not    => do_logical_not(bus, a),
+            Operator::And    => do_logical_and(bus, a, b),
+            Operator::Or     => do_logical_or(bus, a, b),
+            Operator::RShift => do_logical_rshift(bus, a, b),
+            Operator::LShift => do_logical_lshift(bus, a, b),
+            Operator::Xor    => do_logical_xor(bus, a, b),
+            Operator::Nand   => do_logical_nand(bus, a, b),
+            Operator::Nor    => do_logical_nor(bus, a, b),
+            Operator::RotL   => do_logical_rotl(bus, a, b),
+            Operator::RotR   => do_logical_rotr(bus, a, b),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum CircuitError {
+    /// The wires form a loop, listed in signal direction.
+    Cycle(Vec<String>),
+    /// The wire has no source but is read by the listed wires.
+    Undefined { wire: String, used_by: Vec<String> },
+    /// More than one instruction drives the wire.
+    MultipleDrivers { wire: String, count: usize },
+}
+
+impl std::fmt::Display for CircuitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CircuitError::Cycle(wires) => write!(f, "cycle {} -> {}", wires.join(" -> "), wires[0]),
+            CircuitError::Undefined { wire, used_by } => write!(f, "wire {} is used by {} but never driven", wire, used_by.join(", ")),
+            CircuitError::MultipleDrivers { wire, count } => write!(f, "wire {} is driven by {} sources", wire, count),
         }
     }
 }
 
+/// A circuit compiled into an evaluation plan: every wire gets an id, and
+/// `order` lists the wires so that each gate comes after its inputs.
+struct Circuit {
+    names: Vec<String>,
+    ids: HashMap<String, usize>,
+    gates: Vec<Gate>,
+    order: Vec<usize>,
+}
+
+impl Circuit {
+    /// Checks the instructions and sorts them topologically. All problems
+    /// are reported at once.
+    fn compile(instructions: &[Instruction]) -> Result<Circuit, Vec<CircuitError>> {
+        let mut errors = Vec::new();
+        let mut ids: HashMap<String, usize> = HashMap::new();
+        let mut names: Vec<String> = Vec::new();
+        let mut drivers: Vec<usize> = Vec::new();
+
+        for inst in instructions {
+            let id = *ids.entry(inst.lbl.clone()).or_insert_with(|| {
+                names.push(inst.lbl.clone());
+                drivers.push(0);
+                names.len() - 1
+            });
+            drivers[id] += 1;
+        }
+        for (id, &count) in drivers.iter().enumerate().filter(|(_, &c)| c > 1) {
+            errors.push(CircuitError::MultipleDrivers { wire: names[id].clone(), count });
+        }
+
+        let mut undefined: Vec<(String, Vec<String>)> = Vec::new();
+        let mut gates: Vec<Option<Gate>> = vec![None; names.len()];
+        for inst in instructions {
+            let mut input = |o: &Operand| match o {
+                Operand::Number(n) => Input::Const(*n),
+                Operand::Label(l) => match ids.get(l) {
+                    Some(&id) => Input::Wire(id),
+                    None => {
+                        match undefined.iter_mut().find(|u| &u.0 == l) {
+                            Some(u) => u.1.push(inst.lbl.clone()),
+                            None => undefined.push((l.clone(), vec![inst.lbl.clone()])),
+                        }
+                        Input::Const(0)
+                    },
+                },
+            };
+            let gate = Gate { op: inst.op.clone(), a: input(&inst.ora), b: inst.orb.as_ref().map(input) };
+            gates[ids[&inst.lbl]].get_or_insert(gate);
+        }
+        errors.extend(undefined.into_iter().map(|(wire, used_by)| CircuitError::Undefined { wire, used_by }));
+
+        let gates: Vec<Gate> = gates.into_iter().map(Option::unwrap).collect();
+        let (order, cycles) = topological_order(&gates);
+        errors.extend(cycles.into_iter().map(|c| CircuitError::Cycle(c.iter().map(|&w| names[w].clone()).collect())));
 
-fn do_logical_and(ora: &Operand, orb: &Operand) -> Operand {
-    let Operand::Number(a) = *ora else { todo!( )};
-    let Operand::Number(b) = *orb else { todo!( )};
-    Operand::Number( a & b)
-} 
-
-fn do_logical_or(ora: &Operand, orb: &Operand) -> Operand {
-    let Operand::Number(a) = *ora else { todo!( )};
-    let Operand::Number(b) = *orb else { todo!( )};
-    Operand::Number( a | b)
-} 
-
-fn do_logical_not(ora: &Operand) -> Operand {
-    let Operand::Number(a) = *ora else { todo!( )};
-    Operand::Number( !a )
-} 
-
-fn do_logical_lshift(ora: &Operand, orb: &Operand) -> Operand {
-    let Operand::Number(a) = *ora else { todo!( )};
-    let Operand::Number(b) = *orb else { todo!( )};
-    Operand::Number( a << b)
-} 
-
-fn do_logical_rshift(ora: &Operand, orb: &Operand) -> Operand {
-    let Operand::Number(a) = *ora else { todo!( )};
-    let Operand::Number(b) = *orb else { todo!( )};
-    Operand::Number( a >> b)
-} 
-
-
-fn eval_instructions(instructions: &HashMap<String, Instruction>, sig: &str) -> i32{
-    let mut stack: Vec<Instruction> = Vec::new();
-    let mut results: HashMap<String, Operand> = HashMap::new();
-    let mut inst = instructions.get(sig).unwrap().clone();
-    let mut res: Operand;
-    
-    loop {
-        if let Operand::Label(l) = &inst.ora {
-            if let Some(r) = results.get(l) {
-                inst.ora = r.clone();
-            } else {
-                stack.push(inst.clone());
-                inst = instructions.get(l).unwrap().clone();
+        if errors.is_empty() {
+            Ok(Circuit { names, ids, gates, order })
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Values of all wires, indexed by wire id, in one pass over the plan.
+    fn evaluate(&self, bus: Bus) -> Vec<Signal> {
+        let mut values = vec![Signal(0); self.gates.len()];
+        for &w in self.order.iter() {
+            values[w] = self.gates[w].eval(bus, &values);
+        }
+        values
+    }
+
+    fn signal(&self, values: &[Signal], wire: &str) -> Option<Signal> {
+        self.ids.get(wire).map(|&id| values[id])
+    }
+
+    /// Marks every wire that influences `wire`, including itself.
+    fn fan_in(&self, wire: usize) -> Vec<bool> {
+        let mut cone = vec![false; self.gates.len()];
+        let mut todo = vec![wire];
+        while let Some(w) = todo.pop() {
+            if !cone[w] {
+                cone[w] = true;
+                todo.extend(self.gates[w].inputs());
             }
-        } else if let Some(Operand::Label(l)) = inst.orb.as_ref() {
-            if let Some(r) = results.get(l) {
-                inst.orb = Some(r.clone());
-            } else {
-                stack.push(inst.clone());
-                inst = instructions.get(l).unwrap().clone();
+        }
+        cone
+    }
+
+    /// Wires without wire inputs (driven by constants) that influence
+    /// `wire`, sorted by name.
+    fn input_wires(&self, wire: usize) -> Vec<&str> {
+        let cone = self.fan_in(wire);
+        let mut inputs: Vec<&str> = (0..self.gates.len())
+            .filter(|&w| cone[w] && self.gates[w].inputs().next().is_none())
+            .map(|w| self.names[w].as_str())
+            .collect();
+        inputs.sort();
+        inputs
+    }
+
+    /// Logic depth of every wire: the largest number of gates on a path from
+    /// a constant to the wire. Plain assignments are no gates.
+    fn depths(&self) -> Vec<usize> {
+        let mut depth = vec![0; self.gates.len()];
+        for &w in self.order.iter() {
+            let gate = &self.gates[w];
+            let inputs = gate.inputs().map(|i| depth[i]).max().unwrap_or(0);
+            depth[w] = inputs + (gate.op != Operator::Assign) as usize;
+        }
+        depth
+    }
+
+    /// Graphviz netlist: one node per gate, one edge per wire from the gate
+    /// driving it to each gate reading it. The fan-in cone of `highlight`
+    /// is filled and drawn in red.
+    fn write_dot<W: Write>(&self, out: &mut W, highlight: Option<usize>) -> io::Result<()> {
+        let cone = highlight.map_or(vec![false; self.gates.len()], |w| self.fan_in(w));
+        writeln!(out, "digraph circuit {{")?;
+        writeln!(out, "    rankdir=LR;")?;
+        writeln!(out, "    node [shape=box, fontname=monospace];")?;
+        for (w, gate) in self.gates.iter().enumerate() {
+            let constant = |i: Option<Input>| match i {
+                Some(Input::Const(c)) => format!(" {}", c),
+                _ => String::new(),
+            };
+            let label = match gate.op {
+                Operator::Assign => constant(Some(gate.a)).trim().to_owned(),
+                ref op => format!("{}{}{}", operator_name(op), constant(Some(gate.a)), constant(gate.b)),
+            };
+            let style = if cone[w] { ", style=filled, fillcolor=lightpink" } else { "" };
+            writeln!(out, "    \"{}\" [label=\"{}\\n{}\"{}];", self.names[w], label, self.names[w], style)?;
+        }
+        for (w, gate) in self.gates.iter().enumerate() {
+            for i in gate.inputs() {
+                let style = if cone[w] && cone[i] { ", color=red, fontcolor=red" } else { "" };
+                writeln!(out, "    \"{}\" -> \"{}\" [label=\"{}\"{}];", self.names[i], self.names[w], self.names[i], style)?;
             }
-        } else {
-            if Operator::Assign == inst.op {
-               res = inst.ora;
-            } else if Operator::Not == inst.op {
-                res = do_logical_not(&inst.ora);
-            } else if Operator::And == inst.op {
-                res = do_logical_and(&inst.ora, inst.orb.as_ref().unwrap());
-            } else if Operator::Or == inst.op {
-                res = do_logical_or(&inst.ora, inst.orb.as_ref().unwrap());
-            } else if Operator::RShift == inst.op {
-                res = do_logical_rshift(&inst.ora, inst.orb.as_ref().unwrap());
-            } else if Operator::LShift == inst.op {
-                res = do_logical_lshift(&inst.ora, inst.orb.as_ref().unwrap()); 
-            } else {
-                println!("unhandled operator");
-                break;
+        }
+        writeln!(out, "}}")
+    }
+
+    /// For every wire the wires it drives.
+    fn fanout(&self) -> Vec<Vec<usize>> {
+        let mut fanout = vec![Vec::new(); self.gates.len()];
+        for (w, gate) in self.gates.iter().enumerate() {
+            gate.inputs().for_each(|i| fanout[i].push(w));
+        }
+        fanout
+    }
+}
+
+/// A wire whose signal changed.
+#[derive(Debug, Clone, PartialEq)]
+struct Change {
+    wire: String,
+    old: Signal,
+    new: Signal,
+}
+
+/// Keeps the signals of a circuit up to date while wires are overridden
+/// with fixed values. Only the downstream cone of a changed wire is
+/// evaluated again, and propagation stops at wires that keep their signal.
+struct Simulator<'a> {
+    circuit: &'a Circuit,
+    bus: Bus,
+    values: Vec<Signal>,
+    overrides: HashMap<usize, Signal>,
+    fanout: Vec<Vec<usize>>,
+    /// Position of each wire in the evaluation plan.
+    rank: Vec<usize>,
+}
+
+impl<'a> Simulator<'a> {
+    fn new(circuit: &'a Circuit, bus: Bus) -> Simulator<'a> {
+        let mut rank = vec![0; circuit.gates.len()];
+        circuit.order.iter().enumerate().for_each(|(r, &w)| rank[w] = r);
+        Simulator { circuit, bus, values: circuit.evaluate(bus), overrides: HashMap::new(), fanout: circuit.fanout(), rank }
+    }
+
+    fn signal(&self, wire: &str) -> Option<Signal> {
+        self.circuit.signal(&self.values, wire)
+    }
+
+    fn id(&self, wire: &str) -> Result<usize, String> {
+        self.circuit.ids.get(wire).copied().ok_or_else(|| format!("unknown wire {}", wire))
+    }
+
+    /// Drives the wires with fixed values instead of their gates.
+    fn override_wires(&mut self, overrides: &[(&str, u64)]) -> Result<Vec<Change>, String> {
+        let mut seeds = Vec::new();
+        for &(wire, value) in overrides {
+            let id = self.id(wire)?;
+            self.overrides.insert(id, self.bus.signal(value));
+            seeds.push(id);
+        }
+        Ok(self.propagate(seeds))
+    }
+
+    /// Re-evaluates the seeds and, in plan order, every wire driven by a
+    /// wire that changed.
+    fn propagate(&mut self, seeds: Vec<usize>) -> Vec<Change> {
+        let mut queue: BinaryHeap<Reverse<(usize, usize)>> = seeds.into_iter().map(|w| Reverse((self.rank[w], w))).collect();
+        let mut changes = Vec::new();
+
+        while let Some(Reverse((_, w))) = queue.pop() {
+            // a wire can be queued by several inputs
+            if queue.peek().is_some_and(|Reverse((_, next))| *next == w) {
+                continue;
             }
-            results.insert(inst.lbl.clone(), res.clone());
-            if stack.is_empty() {
-                break;
+            let new = match self.overrides.get(&w) {
+                Some(&v) => v,
+                None => self.circuit.gates[w].eval(self.bus, &self.values),
+            };
+            if new != self.values[w] {
+                changes.push(Change { wire: self.circuit.names[w].clone(), old: self.values[w], new });
+                self.values[w] = new;
+                queue.extend(self.fanout[w].iter().map(|&f| Reverse((self.rank[f], f))));
             }
-            inst = stack.pop().unwrap();
-            if let Operand::Label(_) = inst.ora {
-                inst.ora = res;
-            } else if let Some(a) = inst.orb.as_ref() {
-                if let Operand::Label(_) = a {
-                    inst.orb = Some(res);
-                }
-            } else {
-                println!{"error"};
+        }
+        changes
+    }
+}
+
+fn parse_override(s: &str) -> Result<(&str, u64), String> {
+    let (wire, value) = s.split_once('=').ok_or_else(|| format!("{:?} is not WIRE=VALUE", s))?;
+    value.trim().parse().map(|v| (wire.trim(), v)).map_err(|_| format!("{:?} is not a number", value))
+}
+
+/// Depth-first topological sort. Returns the order of all wires that are
+/// not caught in a cycle, and each cycle found as list of wire ids.
+fn topological_order(gates: &[Gate]) -> (Vec<usize>, Vec<Vec<usize>>) {
+    #[derive(Clone, Copy, PartialEq)]
+    enum Mark { New, Active, Done }
+
+    let mut mark = vec![Mark::New; gates.len()];
+    let mut order = Vec::with_capacity(gates.len());
+    let mut cycles = Vec::new();
+
+    for root in 0..gates.len() {
+        if mark[root] != Mark::New {
+            continue;
+        }
+        // path of active wires with the inputs that are still to visit
+        let mut path: Vec<(usize, Vec<usize>)> = vec![(root, gates[root].inputs().collect())];
+        mark[root] = Mark::Active;
+        while let Some((wire, pending)) = path.last_mut() {
+            match pending.pop() {
+                Some(next) => match mark[next] {
+                    Mark::New => {
+                        mark[next] = Mark::Active;
+                        path.push((next, gates[next].inputs().collect()));
+                    },
+                    Mark::Active => {
+                        // signals flow from the inputs to the wire, so the
+                        // cycle is the path from `next` reversed
+                        let start = path.iter().position(|(w, _)| *w == next).unwrap();
+                        cycles.push(path[start..].iter().rev().map(|(w, _)| *w).collect());
+                    },
+                    Mark::Done => (),
+                },
+                None => {
+                    mark[*wire] = Mark::Done;
+                    order.push(*wire);
+                    path.pop();
+                },
             }
         }
     }
-    if let Operand::Number(n) = results.get(sig).unwrap() {*n} else {-1}
+    // wires downstream of a cycle would read values that never settle
+    if !cycles.is_empty() {
+        let mut broken = vec![false; gates.len()];
+        cycles.iter().flatten().for_each(|&w| broken[w] = true);
+        order.retain(|&w| {
+            broken[w] |= gates[w].inputs().any(|i| broken[i]);
+            !broken[w]
+        });
+    }
+    (order, cycles)
 }
 
 
@@ -271,12 +655,346 @@ where P: AsRef<std::path::Path>, {
     y.collect()
 }
 
+fn compile_or_exit(instructions: &[Instruction]) -> Circuit {
+    match Circuit::compile(instructions) {
+        Ok(c) => c,
+        Err(errors) => {
+            errors.iter().for_each(|e| eprintln!("{}", e));
+            std::process::exit(1);
+        },
+    }
+}
+
+/// Simplified form of a wire while optimising.
+#[derive(Debug, Clone, PartialEq)]
+enum Form {
+    Const(u64),
+    /// Carries the same signal as another wire.
+    Alias(usize),
+    Gate(Gate),
+}
+
+/// Folds constants, replaces chains of assignments by the wire at their
+/// start and simplifies identities like `x AND x`, `x OR 0`, `x LSHIFT 0`
+/// or `NOT NOT x`. Every wire keeps its signal. As all wires of a complete
+/// circuit stem from constants, wires listed in `free` are treated as
+/// inputs whose value is unknown, e.g. `b` to keep part two open. With
+/// `keep` only the listed wires and the wires they depend on remain.
+fn optimise(instructions: &[Instruction], bus: Bus, free: &[&str], keep: Option<&[&str]>) -> Result<Vec<Instruction>, Vec<CircuitError>> {
+    let circuit = Circuit::compile(instructions)?;
+    let mut forms: Vec<Form> = vec![Form::Const(0); circuit.gates.len()];
+
+    let is_free: Vec<bool> = circuit.names.iter().map(|n| free.contains(&n.as_str())).collect();
+
+    for &w in circuit.order.iter() {
+        if is_free[w] {
+            forms[w] = Form::Gate(circuit.gates[w].clone());
+            continue;
+        }
+        let resolve = |i: Input| match i {
+            Input::Wire(r) => match forms[r] {
+                Form::Const(c) => Input::Const(c),
+                Form::Alias(a) => Input::Wire(a),
+                Form::Gate(_) => Input::Wire(r),
+            },
+            c => c,
+        };
+        let gate = &circuit.gates[w];
+        let (a, b) = (resolve(gate.a), gate.b.map(resolve));
+        let (zero, ones) = (Input::Const(0), Input::Const(bus.mask()));
+        let folded = Gate { op: gate.op.clone(), a, b };
+        forms[w] = match (&gate.op, a, b) {
+            (_, Input::Const(_), None | Some(Input::Const(_))) => Form::Const(folded.eval(bus, &[]).0),
+            (Operator::Assign, Input::Wire(r), _) => Form::Alias(r),
+            (Operator::Not, Input::Wire(r), _) => match &forms[r] {
+                Form::Gate(Gate { op: Operator::Not, a: Input::Wire(x), .. }) if !is_free[r] => Form::Alias(*x),
+                _ => Form::Gate(folded),
+            },
+            (Operator::And | Operator::Or, Input::Wire(r), Some(y)) if Input::Wire(r) == y => Form::Alias(r),
+            (Operator::Xor, x, Some(y)) if x == y => Form::Const(0),
+            (Operator::Xor, Input::Wire(r), Some(o)) | (Operator::Xor, o, Some(Input::Wire(r))) if o == zero => Form::Alias(r),
+            (Operator::And, x, Some(y)) if x == zero || y == zero => Form::Const(0),
+            (Operator::Or, x, Some(y)) if x == ones || y == ones => Form::Const(bus.mask()),
+            (Operator::And, Input::Wire(r), Some(o)) | (Operator::And, o, Some(Input::Wire(r))) if o == ones => Form::Alias(r),
+            (Operator::Or, Input::Wire(r), Some(o)) | (Operator::Or, o, Some(Input::Wire(r))) if o == zero => Form::Alias(r),
+            (Operator::LShift | Operator::RShift, Input::Wire(r), Some(Input::Const(0))) => Form::Alias(r),
+            (Operator::LShift | Operator::RShift, _, Some(Input::Const(s))) if s >= bus.width as u64 => Form::Const(0),
+            (Operator::LShift | Operator::RShift, x, _) if x == zero => Form::Const(0),
+            (Operator::RotL | Operator::RotR, Input::Wire(r), Some(Input::Const(s))) if s % bus.width as u64 == 0 => Form::Alias(r),
+            (Operator::RotL | Operator::RotR, x, _) if x == zero || x == ones => Form::Const(if x == zero { 0 } else { bus.mask() }),
+            _ => Form::Gate(folded),
+        };
+    }
+
+    let mut needed = vec![keep.is_none(); forms.len()];
+    for wire in keep.unwrap_or(&[]) {
+        let mut todo: Vec<usize> = circuit.ids.get(*wire).copied().into_iter().collect();
+        while let Some(w) = todo.pop() {
+            if !needed[w] {
+                needed[w] = true;
+                match &forms[w] {
+                    Form::Alias(a) => todo.push(*a),
+                    Form::Gate(g) => todo.extend(g.inputs()),
+                    Form::Const(_) => (),
+                }
+            }
+        }
+    }
+
+    let operand = |i: Input| match i {
+        Input::Wire(w) => Operand::Label(circuit.names[w].clone()),
+        Input::Const(c) => Operand::Number(c),
+    };
+    Ok((0..forms.len()).filter(|&w| needed[w]).map(|w| {
+        let lbl = circuit.names[w].clone();
+        match &forms[w] {
+            Form::Const(c) => Instruction { lbl, op: Operator::Assign, ora: Operand::Number(*c), orb: None },
+            Form::Alias(a) => Instruction { lbl, op: Operator::Assign, ora: operand(Input::Wire(*a)), orb: None },
+            Form::Gate(g) => Instruction { lbl, op: g.op.clone(), ora: operand(g.a), orb: g.b.map(operand) },
+        }
+    }).collect())
+}
+
+/// Answers `inputs WIRE` (input wires influencing the wire), `depth WIRE`
+/// (logic depth) and `cone WIRE` (all wires influencing the wire).
+fn answer(circuit: &Circuit, query: &str) -> Result<String, String> {
+    let (kind, wire) = query.split_once(' ').ok_or_else(|| format!("{:?} is not QUERY WIRE", query))?;
+    let w = *circuit.ids.get(wire.trim()).ok_or_else(|| format!("unknown wire {}", wire))?;
+    match kind {
+        "inputs" => Ok(circuit.input_wires(w).join(", ")),
+        "depth" => Ok(circuit.depths()[w].to_string()),
+        "cone" => {
+            let cone = circuit.fan_in(w);
+            let mut wires: Vec<&str> = (0..cone.len()).filter(|&i| cone[i]).map(|i| circuit.names[i].as_str()).collect();
+            wires.sort();
+            Ok(format!("{} wires: {}", wires.len(), wires.join(", ")))
+        },
+        _ => Err(format!("unknown query {:?}", kind)),
+    }
+}
+
+/// Usage: day07 [--input FILE] [--width N] [--all] [--override WIRE=VALUE]...
+///              [--dot FILE [--highlight WIRE]] [--query QUERY]...
+///              [--optimise FILE [--free WIRE]... [--keep WIRE]...]
+///
+/// Solves both parts on a bus of N bits (default 16). `--all` lists the
+/// signals of all wires of part one. The overrides are applied to the
+/// circuit of part one and the wires they change are listed. `--dot`
+/// writes the netlist for Graphviz, optionally with the fan-in cone of a
+/// wire highlighted; see `answer` for the queries. `--optimise` writes the
+/// simplified netlist (see `optimise`), with the free wires as inputs and
+/// reduced to the kept wires if any.
 fn main (){
-    let mut instructions: HashMap<String, Instruction> = HashMap::new();
-    let actions: Vec<Command> = read_input("data/07/input.txt", Command::new);
-    actions.iter().for_each(|e| {instructions.insert(e.rhs.clone(), Instruction::new(e.lhs.as_ref(), &e.rhs));} );
-    let sig_a = eval_instructions(&instructions, "a");
-    println!("Leitung a hat das Signal {:4}", sig_a);
-    instructions.get_mut("b").unwrap().ora = Operand::Number(sig_a);
-    println!("Leitung a hat nach {} -> b das Signal {:4}", sig_a, eval_instructions(&instructions,"a"));
-}
\ No newline at end of file
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let bus = match args.iter().position(|a| a == "--width").and_then(|i| args.get(i + 1)) {
+        Some(w) => Bus::new(w.parse().expect("bus width")).unwrap_or_else(|e| panic!("{}", e)),
+        None => Bus::PUZZLE,
+    };
+    let input = args.iter().position(|a| a == "--input").and_then(|i| args.get(i + 1));
+    let lines: Vec<Result<Command, String>> = read_input(input.map_or("data/07/input.txt", String::as_str), Command::new);
+    let parsed: Vec<Result<Instruction, String>> = lines.into_iter()
+        .map(|c| c.and_then(|c| Instruction::new(&c.lhs, &c.rhs)))
+        .collect();
+    if parsed.iter().any(Result::is_err) {
+        parsed.iter().enumerate().filter_map(|(idx, r)| r.as_ref().err().map(|e| (idx, e)))
+            .for_each(|(idx, e)| eprintln!("line {}: {}", idx + 1, e));
+        std::process::exit(1);
+    }
+    let instructions: Vec<Instruction> = parsed.into_iter().map(Result::unwrap).collect();
+    let circuit = compile_or_exit(&instructions);
+    if let Some(f) = args.iter().position(|a| a == "--dot").and_then(|i| args.get(i + 1)) {
+        let highlight = args.iter().position(|a| a == "--highlight").and_then(|i| args.get(i + 1))
+            .map(|w| *circuit.ids.get(w).unwrap_or_else(|| panic!("unknown wire {}", w)));
+        let mut out = BufWriter::new(File::create(f).expect("cannot create DOT file"));
+        circuit.write_dot(&mut out, highlight).expect("DOT export failed");
+    }
+    if let Some(f) = args.iter().position(|a| a == "--optimise").and_then(|i| args.get(i + 1)) {
+        let keep: Vec<&str> = args.windows(2).filter(|w| w[0] == "--keep").map(|w| w[1].as_str()).collect();
+        let free: Vec<&str> = args.windows(2).filter(|w| w[0] == "--free").map(|w| w[1].as_str()).collect();
+        let reduced = optimise(&instructions, bus, &free, (!keep.is_empty()).then_some(&keep[..])).unwrap();
+        let gates = |v: &[Instruction]| v.iter().filter(|i| i.op != Operator::Assign).count();
+        println!("Optimised {} wires with {} gates to {} wires with {} gates",
+                 instructions.len(), gates(&instructions), reduced.len(), gates(&reduced));
+        let mut out = BufWriter::new(File::create(f).expect("cannot create netlist"));
+        reduced.iter().try_for_each(|i| writeln!(out, "{}", i)).expect("netlist export failed");
+    }
+    for q in args.windows(2).filter(|w| w[0] == "--query").map(|w| &w[1]) {
+        match answer(&circuit, q) {
+            Ok(a) => println!("{}: {}", q, a),
+            Err(e) => println!("{}: {}", q, e),
+        }
+    }
+    let mut sim = Simulator::new(&circuit, bus);
+    if args.iter().any(|a| a == "--all") {
+        let mut wires: Vec<(&String, Signal)> = circuit.names.iter().zip(sim.values.iter().copied()).collect();
+        wires.sort();
+        wires.iter().for_each(|(name, v)| println!("{}: {}", name, v.0));
+    }
+    let overrides: Vec<(&str, u64)> = args.windows(2).filter(|w| w[0] == "--override")
+        .map(|w| parse_override(&w[1]).unwrap_or_else(|e| panic!("{}", e)))
+        .collect();
+    if !overrides.is_empty() {
+        let changes = Simulator::new(&circuit, bus).override_wires(&overrides).unwrap_or_else(|e| panic!("{}", e));
+        println!("Overrides changed {} wires", changes.len());
+        changes.iter().for_each(|c| println!("{}: {} -> {}", c.wire, c.old.0, c.new.0));
+    }
+
+    let sig_a = sim.signal("a").expect("no wire a");
+    println!("Leitung a hat das Signal {:4}", sig_a.0);
+    sim.override_wires(&[("b", sig_a.0)]).expect("no wire b");
+    println!("Leitung a hat nach {} -> b das Signal {:4}", sig_a.0, sim.signal("a").unwrap().0);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "123 -> x\n456 -> y\nx AND y -> d\nx OR y -> e\nx LSHIFT 2 -> f\ny RSHIFT 2 -> g\nNOT x -> h\nNOT y -> i";
+
+    fn parse(text: &str) -> Vec<Instruction> {
+        text.lines().map(|l| Command::new(l).and_then(|c| Instruction::new(&c.lhs, &c.rhs)).unwrap()).collect()
+    }
+
+    fn eval(instructions: &[Instruction], wire: &str, bus: Bus) -> Signal {
+        let circuit = Circuit::compile(instructions).unwrap();
+        circuit.signal(&circuit.evaluate(bus), wire).unwrap()
+    }
+
+    #[test]
+    fn test_example() {
+        let instructions = parse(EXAMPLE);
+        let expected = [("d", 72), ("e", 507), ("f", 492), ("g", 114), ("h", 65412), ("i", 65079), ("x", 123), ("y", 456)];
+        for (wire, value) in expected {
+            assert_eq!(eval(&instructions, wire, Bus::PUZZLE), Signal(value), "wire {}", wire);
+        }
+    }
+
+    #[test]
+    fn test_bus_width() {
+        let instructions = parse("200 -> x\nx LSHIFT 1 -> y\nNOT x -> z\nx LSHIFT 70 -> w");
+        let bus8 = Bus::new(8).unwrap();
+        assert_eq!(eval(&instructions, "y", bus8), Signal(144));
+        assert_eq!(eval(&instructions, "z", bus8), Signal(55));
+        assert_eq!(eval(&instructions, "y", Bus::PUZZLE), Signal(400));
+        assert_eq!(eval(&instructions, "z", Bus::new(64).unwrap()), Signal(!200));
+        assert_eq!(eval(&instructions, "w", Bus::new(64).unwrap()), Signal(0));
+        assert!(Bus::new(65).is_err());
+    }
+
+    #[test]
+    fn test_compile_errors() {
+        let errors = Circuit::compile(&parse("a -> b\nb AND c -> a\n1 -> c\nq OR r -> x\nx -> y\n2 -> y")).err().unwrap();
+        assert_eq!(errors, vec![
+            CircuitError::MultipleDrivers { wire: "y".to_owned(), count: 2 },
+            CircuitError::Undefined { wire: "q".to_owned(), used_by: vec!["x".to_owned()] },
+            CircuitError::Undefined { wire: "r".to_owned(), used_by: vec!["x".to_owned()] },
+            CircuitError::Cycle(vec!["a".to_owned(), "b".to_owned()]),
+        ]);
+        assert_eq!(errors[3].to_string(), "cycle a -> b -> a");
+        let errors = Circuit::compile(&parse("x -> x")).err().unwrap();
+        assert_eq!(errors, vec![CircuitError::Cycle(vec!["x".to_owned()])]);
+    }
+
+    #[test]
+    fn test_overrides() {
+        let circuit = Circuit::compile(&parse(EXAMPLE)).unwrap();
+        let mut sim = Simulator::new(&circuit, Bus::PUZZLE);
+        let changes = sim.override_wires(&[("x", 127)]).unwrap();
+        let changed: Vec<&str> = changes.iter().map(|c| c.wire.as_str()).collect();
+        // x AND y stays 72
+        assert_eq!(changed, vec!["x", "e", "f", "h"]);
+        assert_eq!(changes[1], Change { wire: "e".to_owned(), old: Signal(507), new: Signal(511) });
+        let rewired = Circuit::compile(&parse(&EXAMPLE.replace("123 -> x", "127 -> x"))).unwrap();
+        assert_eq!(sim.values, rewired.evaluate(Bus::PUZZLE));
+        assert!(sim.override_wires(&[("x", 127)]).unwrap().is_empty());
+        assert!(sim.override_wires(&[("nope", 1)]).is_err());
+        assert_eq!(parse_override("b=46065"), Ok(("b", 46065)));
+        assert!(parse_override("b:1").is_err());
+    }
+
+    #[test]
+    fn test_queries() {
+        let circuit = Circuit::compile(&parse(&(EXAMPLE.to_owned() + "\nd OR f -> j\nj -> k\n7 -> z"))).unwrap();
+        assert_eq!(answer(&circuit, "inputs k"), Ok("x, y".to_owned()));
+        assert_eq!(answer(&circuit, "depth k"), Ok("2".to_owned()));
+        assert_eq!(answer(&circuit, "depth x"), Ok("0".to_owned()));
+        assert_eq!(answer(&circuit, "cone f"), Ok("2 wires: f, x".to_owned()));
+        assert!(answer(&circuit, "depth q").is_err());
+
+        let mut dot = Vec::new();
+        circuit.write_dot(&mut dot, circuit.ids.get("f").copied()).unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+        assert!(dot.contains("\"f\" [label=\"LSHIFT 2\\nf\", style=filled, fillcolor=lightpink];"));
+        assert!(dot.contains("\"x\" -> \"f\" [label=\"x\", color=red, fontcolor=red];"));
+        assert!(dot.contains("\"y\" -> \"g\" [label=\"y\"];"));
+    }
+
+    #[test]
+    fn test_optimise() {
+        let text = "5 -> x\n3 -> c\nc LSHIFT 2 -> d\nx -> y\ny -> z\nz AND z -> e\ne OR 0 -> f\nf LSHIFT 0 -> g\nNOT g -> h\nNOT h -> i\nd OR i -> j\ni AND 65535 -> k";
+        let instructions = parse(text);
+        let reduced = optimise(&instructions, Bus::PUZZLE, &["x"], None).unwrap();
+        let lines: Vec<String> = reduced.iter().map(|i| i.to_string()).collect();
+        assert_eq!(lines, vec!["5 -> x", "3 -> c", "12 -> d", "x -> y", "x -> z", "x -> e", "x -> f", "x -> g",
+                               "NOT x -> h", "x -> i", "12 OR x -> j", "x -> k"]);
+
+        let keep = optimise(&instructions, Bus::PUZZLE, &["x"], Some(&["j"])).unwrap();
+        assert_eq!(keep.iter().map(|i| i.to_string()).collect::<Vec<_>>(), vec!["5 -> x", "12 OR x -> j"]);
+        let folded = optimise(&instructions, Bus::PUZZLE, &[], Some(&["j"])).unwrap();
+        assert_eq!(folded.iter().map(|i| i.to_string()).collect::<Vec<_>>(), vec!["13 -> j"]);
+    }
+
+    #[test]
+    fn test_optimise_free_not() {
+        let instructions = parse("5 -> y\nNOT y -> b\nNOT b -> a");
+        let reduced = optimise(&instructions, Bus::PUZZLE, &["b"], None).unwrap();
+        assert_eq!(reduced[2].to_string(), "NOT b -> a");
+        let rewire = |netlist: &[Instruction]| -> Vec<Instruction> {
+            netlist.iter().map(|i| if i.lbl == "b" { parse("1 -> b").remove(0) } else { i.clone() }).collect()
+        };
+        assert_eq!(eval(&rewire(&instructions), "a", Bus::PUZZLE), Signal(65534));
+        assert_eq!(eval(&rewire(&reduced), "a", Bus::PUZZLE), Signal(65534));
+    }
+
+    #[test]
+    fn test_optimise_keeps_values() {
+        let text = EXAMPLE.to_owned() + "\nd AND d -> j\nj OR 0 -> k\nk LSHIFT 0 -> l\nNOT h -> m\nl AND m -> n";
+        for bus in [Bus::PUZZLE, Bus::new(8).unwrap()] {
+            let circuit = Circuit::compile(&parse(&text)).unwrap();
+            let reduced = Circuit::compile(&optimise(&parse(&text), bus, &["x"], None).unwrap()).unwrap();
+            let (before, after) = (circuit.evaluate(bus), reduced.evaluate(bus));
+            for name in circuit.names.iter() {
+                assert_eq!(circuit.signal(&before, name), reduced.signal(&after, name), "wire {}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn test_new_gates() {
+        let text = "12 -> x\n10 -> y\nx XOR y -> a\nx NAND y -> b\nx NOR y -> c\nx ROTL 14 -> d\nx ROTR 3 -> e\nx ROTR 35 -> f";
+        let instructions = parse(text);
+        let circuit = Circuit::compile(&instructions).unwrap();
+        let values = circuit.evaluate(Bus::PUZZLE);
+        let expected = [("a", 6), ("b", 65527), ("c", 65521), ("d", 3), ("e", 32769), ("f", 32769)];
+        for (wire, value) in expected {
+            assert_eq!(circuit.signal(&values, wire), Some(Signal(value)), "wire {}", wire);
+        }
+        assert_eq!(circuit.gates[circuit.ids["d"]].eval(Bus::new(8).unwrap(), &values), Signal(3));
+        assert_eq!(instructions[2].to_string(), "x XOR y -> a");
+
+        let reduced = optimise(&parse("1 -> x\nx XOR x -> a\nx XOR 0 -> b\nx ROTL 16 -> c\n0 ROTR x -> d"), Bus::PUZZLE, &["x"], None).unwrap();
+        let lines: Vec<String> = reduced.iter().map(|i| i.to_string()).collect();
+        assert_eq!(lines, vec!["1 -> x", "0 -> a", "x -> b", "x -> c", "0 -> d"]);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(Instruction::new("x XAND y", "z").err(), Some("unknown operator \"XAND\"".to_owned()));
+        assert!(Instruction::new("x NOT y", "z").is_err());
+        assert!(Instruction::new("ROTL y", "z").is_err());
+        assert!(Instruction::new("x AND y z", "z").is_err());
+        assert!(Instruction::new("x", "").is_err());
+        assert!(Command::new("x AND y").is_err());
+    }
+}
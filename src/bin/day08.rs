@@ -64,73 +64,540 @@
 
 
 
-use std::borrow::{Borrow, BorrowMut};
+use std::fmt;
 use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
 
 
-fn count(s: &str) -> u32 {
-    let mut count: usize = 0;
-    let mut idx: usize = 0;
-    let last: usize = s.len() - 1;
-    let ch = s.as_bytes();
-    
-    if ch[0] == '\"' as u8 {
-        idx +=1;
-    }
-    if ch[last] != '\"' as u8 {
-        println!("error at line end");
-        return 0 as u32;
-    }
-    
-    while idx < last {
-        if ch[idx] == '\\' as u8 {
-            if ch[idx+1] == '\\' as u8 || ch[idx+1] == '\"' as u8 {
-                idx += 2;
-            } else if ch[idx+1] == 'x' as u8 {
-                idx += 4;
-            } else {
-                println!("error");
-            }
-        } else {
-            idx += 1;
+/// What went wrong while decoding or encoding a string literal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ErrorKind {
+    /// The literal does not start with a double quote.
+    MissingQuote,
+    /// The closing double quote is missing.
+    Unterminated,
+    /// A backslash followed by a character without meaning.
+    BadEscape(char),
+    /// `\x`, `\u` or `\U` without enough hexadecimal digits.
+    TruncatedHex,
+    /// An escaped value the dialect cannot hold, like a lone surrogate.
+    OutOfRange,
+    /// Characters after the closing quote.
+    Trailing,
+    /// Bytes that are no valid UTF-8 in a dialect of Unicode strings.
+    Unencodable,
+}
+
+/// Decoding error with the byte offset in the literal where it happened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct LiteralError {
+    pos: usize,
+    kind: ErrorKind,
+}
+
+impl fmt::Display for LiteralError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            ErrorKind::MissingQuote  => write!(f, "column {}: expected opening quote", self.pos + 1),
+            ErrorKind::Unterminated  => write!(f, "column {}: unterminated string literal", self.pos + 1),
+            ErrorKind::BadEscape(c)  => write!(f, "column {}: unknown escape \\{}", self.pos + 1, c),
+            ErrorKind::TruncatedHex  => write!(f, "column {}: escape needs more hex digits", self.pos + 1),
+            ErrorKind::OutOfRange    => write!(f, "column {}: escaped value out of range", self.pos + 1),
+            ErrorKind::Trailing      => write!(f, "column {}: characters after closing quote", self.pos + 1),
+            ErrorKind::Unencodable   => write!(f, "byte {}: not valid UTF-8", self.pos),
         }
-        count += 1;
     }
-    (s.len() - count) as u32
 }
 
-fn expand(s: &str) -> u32 {
+/// Escape rules of a string literal syntax. All of them use double quotes
+/// and escape `\\` and `\"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Dialect {
+    /// The puzzle: only `\\`, `\"` and `\xHH`.
+    Puzzle,
+    /// C byte strings: named escapes, octal `\ooo` and `\x` with any number of digits.
+    C,
+    /// JSON: named escapes and `\uXXXX` with surrogate pairs.
+    Json,
+    /// Rust: `\n`, `\r`, `\t`, `\0`, ASCII `\xHH` and `\u{...}`.
+    Rust,
+    /// Python 3 `str`: named, octal, `\xHH`, `\uXXXX` and `\UXXXXXXXX`.
+    /// Unknown escapes stay as they are.
+    Python,
+}
+
+impl Dialect {
+    const ALL: [Dialect; 5] = [Dialect::Puzzle, Dialect::C, Dialect::Json, Dialect::Rust, Dialect::Python];
+
+    fn name(self) -> &'static str {
+        match self {
+            Dialect::Puzzle => "puzzle",
+            Dialect::C      => "c",
+            Dialect::Json   => "json",
+            Dialect::Rust   => "rust",
+            Dialect::Python => "python",
+        }
+    }
 
-    fn inflate(c: u8) -> String {
-        if c == '\\' as u8 {
-            "\\\\".to_owned()
-        } else if c == '\"' as u8 {
-            "\\\"".to_owned()
+    fn parse(s: &str) -> Option<Dialect> {
+        Dialect::ALL.into_iter().find(|d| d.name().eq_ignore_ascii_case(s))
+    }
+
+    /// Whether literals hold bytes rather than Unicode text.
+    fn is_bytes(self) -> bool {
+        matches!(self, Dialect::Puzzle | Dialect::C)
+    }
+
+    /// Characters in memory: bytes for byte strings, code points otherwise.
+    fn memory_len(self, decoded: &[u8]) -> usize {
+        if self.is_bytes() {
+            decoded.len()
         } else {
-            let a = &[c;1];
-            String::from_utf8_lossy(a).to_string()
+            std::str::from_utf8(decoded).map_or(decoded.len(), |s| s.chars().count())
+        }
+    }
+
+    /// Value of a single-character escape like `\n`.
+    fn named(self, c: u8) -> Option<u8> {
+        let known: &[u8] = match self {
+            Dialect::Puzzle => b"\\\"",
+            Dialect::C      => b"\\\"'?abfnrtv",
+            Dialect::Json   => b"\\\"/bfnrt",
+            Dialect::Rust   => b"\\\"'nrt0",
+            Dialect::Python => b"\\\"'abfnrtv",
+        };
+        known.contains(&c).then_some(match c {
+            b'n' => b'\n',
+            b't' => b'\t',
+            b'r' => b'\r',
+            b'0' => 0,
+            b'a' => 0x07,
+            b'b' => 0x08,
+            b'f' => 0x0c,
+            b'v' => 0x0b,
+            _ => c,
+        })
+    }
+
+    /// Decodes the escape sequence starting with the backslash at `idx` and
+    /// returns the number of bytes it takes in the literal.
+    fn unescape(self, s: &str, idx: usize, out: &mut Vec<u8>) -> Result<usize, LiteralError> {
+        let ch = s.as_bytes();
+        let err = |kind| LiteralError{pos: idx, kind};
+        let push_char = |v: u32, out: &mut Vec<u8>| match char::from_u32(v) {
+            Some(c) => { out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()); Ok(()) },
+            None => Err(err(ErrorKind::OutOfRange)),
+        };
+        let Some(&c) = ch.get(idx + 1) else { return Err(err(ErrorKind::Unterminated)) };
+        if let Some(v) = self.named(c) {
+            out.push(v);
+            return Ok(2);
+        }
+        match (self, c) {
+            (Dialect::Puzzle | Dialect::C | Dialect::Rust | Dialect::Python, b'x') => {
+                let max = if self == Dialect::C { usize::MAX } else { 2 };
+                let (v, n) = digits(ch, idx + 2, 16, max);
+                if n == 0 || (self != Dialect::C && n < 2) {
+                    return Err(err(ErrorKind::TruncatedHex));
+                }
+                match self {
+                    Dialect::Python => push_char(v, out)?,
+                    Dialect::Rust if v > 0x7f => return Err(err(ErrorKind::OutOfRange)),
+                    _ if v > 0xff => return Err(err(ErrorKind::OutOfRange)),
+                    _ => out.push(v as u8),
+                }
+                Ok(2 + n)
+            },
+            (Dialect::C | Dialect::Python, b'0'..=b'7') => {
+                let (v, n) = digits(ch, idx + 1, 8, 3);
+                match self {
+                    _ if v > 0xff => return Err(err(ErrorKind::OutOfRange)),
+                    Dialect::Python => push_char(v, out)?,
+                    _ => out.push(v as u8),
+                }
+                Ok(1 + n)
+            },
+            (Dialect::Json | Dialect::Python, b'u') | (Dialect::Python, b'U') => {
+                let len = if c == b'U' { 8 } else { 4 };
+                let (mut v, n) = digits(ch, idx + 2, 16, len);
+                if n < len {
+                    return Err(err(ErrorKind::TruncatedHex));
+                }
+                let mut used = 2 + len;
+                if self == Dialect::Json && (0xd800..0xdc00).contains(&v) && ch[idx + used..].starts_with(b"\\u") {
+                    let (low, n) = digits(ch, idx + used + 2, 16, 4);
+                    if n == 4 && (0xdc00..0xe000).contains(&low) {
+                        v = 0x10000 + ((v - 0xd800) << 10) + (low - 0xdc00);
+                        used += 6;
+                    }
+                }
+                push_char(v, out)?;
+                Ok(used)
+            },
+            (Dialect::Rust, b'u') => {
+                if ch.get(idx + 2) != Some(&b'{') {
+                    return Err(err(ErrorKind::TruncatedHex));
+                }
+                let (v, n) = digits(ch, idx + 3, 16, 6);
+                if n == 0 || ch.get(idx + 3 + n) != Some(&b'}') {
+                    return Err(err(ErrorKind::TruncatedHex));
+                }
+                push_char(v, out)?;
+                Ok(4 + n)
+            },
+            (Dialect::Python, _) => {
+                out.push(b'\\');
+                Ok(1)
+            },
+            _ => Err(err(ErrorKind::BadEscape(s[idx + 1..].chars().next().unwrap()))),
+        }
+    }
+
+    /// Escaped form of a character, or of a byte for byte strings.
+    fn escape(self, c: u32) -> Escaped {
+        let mut e = Escaped{buf: [0; 12], len: 0};
+        match c {
+            0x5c => e.push(b"\\\\"),
+            0x22 => e.push(b"\\\""),
+            0x20..=0x7e => e.push(&[c as u8]),
+            0x0a if self != Dialect::Puzzle => e.push(b"\\n"),
+            0x09 if self != Dialect::Puzzle => e.push(b"\\t"),
+            0x0d if self != Dialect::Puzzle => e.push(b"\\r"),
+            _ => match self {
+                Dialect::Puzzle => { e.push(b"\\x"); e.push_digits(c, 16, 2); },
+                // octal, since `\x` in C swallows every following hex digit
+                Dialect::C => { e.push(b"\\"); e.push_digits(c, 8, 3); },
+                Dialect::Json if c > 0xffff => {
+                    let v = c - 0x10000;
+                    e.push(b"\\u");
+                    e.push_digits(0xd800 + (v >> 10), 16, 4);
+                    e.push(b"\\u");
+                    e.push_digits(0xdc00 + (v & 0x3ff), 16, 4);
+                },
+                Dialect::Json => { e.push(b"\\u"); e.push_digits(c, 16, 4); },
+                Dialect::Rust => { e.push(b"\\u{"); e.push_digits(c, 16, 1); e.push(b"}"); },
+                Dialect::Python if c < 0x100 => { e.push(b"\\x"); e.push_digits(c, 16, 2); },
+                Dialect::Python if c < 0x10000 => { e.push(b"\\u"); e.push_digits(c, 16, 4); },
+                Dialect::Python => { e.push(b"\\U"); e.push_digits(c, 16, 8); },
+            },
+        }
+        e
+    }
+}
+
+/// Escaped form of a single character, kept on the stack. The longest is a
+/// JSON surrogate pair with 12 bytes.
+struct Escaped {
+    buf: [u8; 12],
+    len: usize,
+}
+
+impl Escaped {
+    fn push(&mut self, s: &[u8]) {
+        self.buf[self.len..self.len + s.len()].copy_from_slice(s);
+        self.len += s.len();
+    }
+
+    /// Appends `v` in `radix` with lowercase digits, zero-padded to `width`.
+    fn push_digits(&mut self, v: u32, radix: u32, width: usize) {
+        let mut n = 1;
+        while n < 8 && radix.pow(n as u32) <= v {
+            n += 1;
+        }
+        let n = n.max(width);
+        for (k, slot) in self.buf[self.len..self.len + n].iter_mut().rev().enumerate() {
+            let d = v / radix.pow(k as u32) % radix;
+            *slot = std::char::from_digit(d, radix).unwrap() as u8;
+        }
+        self.len += n;
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+/// The units a literal escapes one by one: bytes of byte strings or
+/// characters of Unicode strings.
+enum Units<'a> {
+    Bytes(std::slice::Iter<'a, u8>),
+    Chars(std::str::Chars<'a>),
+}
+
+impl Iterator for Units<'_> {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<u32> {
+        match self {
+            Units::Bytes(b) => b.next().map(|&b| b as u32),
+            Units::Chars(c) => c.next().map(|c| c as u32),
+        }
+    }
+}
+
+/// Splits bytes into units. Unicode dialects need valid UTF-8.
+fn units(bytes: &[u8], dialect: Dialect) -> Result<Units<'_>, LiteralError> {
+    if dialect.is_bytes() {
+        Ok(Units::Bytes(bytes.iter()))
+    } else {
+        std::str::from_utf8(bytes)
+            .map(|text| Units::Chars(text.chars()))
+            .map_err(|e| LiteralError{pos: e.valid_up_to(), kind: ErrorKind::Unencodable})
+    }
+}
+
+/// Reads up to `max` digits in `radix` from `from` on and returns the value
+/// and the number of digits read.
+fn digits(ch: &[u8], from: usize, radix: u32, max: usize) -> (u32, usize) {
+    let mut value: u32 = 0;
+    let mut n = 0;
+    while let Some(d) = ch.get(from + n).and_then(|&c| (c as char).to_digit(radix)).filter(|_| n < max) {
+        value = value.saturating_mul(radix).saturating_add(d);
+        n += 1;
+    }
+    (value, n)
+}
+
+/// Decodes a double-quoted literal into the bytes it stands for in memory.
+/// Unicode dialects return the UTF-8 encoding of the string.
+fn decode(s: &str, dialect: Dialect) -> Result<Vec<u8>, LiteralError> {
+    let ch = s.as_bytes();
+    let err = |pos, kind| Err(LiteralError{pos, kind});
+    if ch.first() != Some(&b'"') {
+        return err(0, ErrorKind::MissingQuote);
+    }
+    let mut out = Vec::with_capacity(ch.len());
+    let mut idx: usize = 1;
+    loop {
+        match ch.get(idx) {
+            None => return err(idx, ErrorKind::Unterminated),
+            Some(b'"') => break,
+            Some(b'\\') => idx += dialect.unescape(s, idx, &mut out)?,
+            Some(&c) => { out.push(c); idx += 1; },
         }
     }
+    if idx + 1 < ch.len() {
+        return err(idx + 1, ErrorKind::Trailing);
+    }
+    Ok(out)
+}
+
+/// Length of the literal `write_encoded` produces, without building it.
+fn encoded_len(bytes: &[u8], dialect: Dialect) -> Result<usize, LiteralError> {
+    Ok(units(bytes, dialect)?.map(|c| dialect.escape(c).len).sum::<usize>() + 2)
+}
 
-    let r = s.bytes().map(inflate).collect::<String>();
-    (r.len() + 2 - s.len()) as u32
+/// Writes bytes as a literal that `decode` turns back into the same bytes.
+/// Printable ASCII stays as is, everything else is escaped. Unicode dialects
+/// need valid UTF-8.
+fn write_encoded<W: Write>(w: &mut W, bytes: &[u8], dialect: Dialect) -> io::Result<()> {
+    let units = units(bytes, dialect).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    w.write_all(b"\"")?;
+    for c in units {
+        w.write_all(dialect.escape(c).as_bytes())?;
+    }
+    w.write_all(b"\"")
 }
 
-fn read_input<P, R>(filename: P, s: fn(&str) -> R) -> Vec<R>
-where
-    P: AsRef<std::path::Path>,
-{
-    let file = File::open(filename).expect("no such file");
-    let buf = BufReader::new(file);
-    let x = buf.lines();
-    let y = x.map(|l| s(l.unwrap().as_ref()));
-    y.collect()
+/// Length comparisons of a single literal, in characters.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct Lengths {
+    code: usize,
+    memory: usize,
+    encoded: usize,
 }
 
+impl Lengths {
+    fn measure(s: &str, dialect: Dialect) -> Result<Lengths, LiteralError> {
+        let decoded = decode(s, dialect)?;
+        Ok(Lengths {
+            // byte dialects store raw characters as their UTF-8 bytes
+            code: if dialect.is_bytes() { s.len() } else { s.chars().count() },
+            memory: dialect.memory_len(&decoded),
+            // escaped literals are ASCII, so bytes are characters
+            encoded: encoded_len(s.as_bytes(), dialect)?,
+        })
+    }
+
+    fn add(self, other: Lengths) -> Lengths {
+        Lengths {
+            code: self.code + other.code,
+            memory: self.memory + other.memory,
+            encoded: self.encoded + other.encoded,
+        }
+    }
+
+    /// Characters of code minus characters in memory.
+    fn count(&self) -> usize {
+        self.code - self.memory
+    }
+
+    /// Characters of the re-encoded code minus characters of code.
+    fn expand(&self) -> usize {
+        self.encoded - self.code
+    }
+}
+
+/// Measures every line of `r` and, with `encoded`, writes its encoding
+/// there, one literal per line, holding only a single line in memory.
+/// Literals with errors are passed to `on_error` with their line number and
+/// left out of the total.
+fn measure_stream<R: BufRead>(mut r: R, dialect: Dialect, mut encoded: Option<&mut dyn Write>,
+                              on_error: &mut dyn FnMut(usize, LiteralError)) -> io::Result<Lengths> {
+    let mut total = Lengths::default();
+    let mut line = Vec::new();
+    let mut number = 0;
+    while r.read_until(b'\n', &mut line)? > 0 {
+        number += 1;
+        let bytes = line.strip_suffix(b"\n").unwrap_or(&line);
+        let bytes = bytes.strip_suffix(b"\r").unwrap_or(bytes);
+        if let Some(w) = encoded.as_mut() {
+            write_encoded(w, bytes, dialect)?;
+            w.write_all(b"\n")?;
+        }
+        let measured = std::str::from_utf8(bytes)
+            .map_err(|e| LiteralError{pos: e.valid_up_to(), kind: ErrorKind::Unencodable})
+            .and_then(|text| Lengths::measure(text, dialect));
+        match measured {
+            Ok(lengths) => total = total.add(lengths),
+            Err(e) => on_error(number, e),
+        }
+        line.clear();
+    }
+    if let Some(w) = encoded {
+        w.flush()?;
+    }
+    Ok(total)
+}
+
+/// Usage: day08 [--input FILE] [--dialect puzzle|c|json|rust|python]
+///              [--encode FILE|-]
+///
+/// Solves both parts for the literals of the input, one per line, read with
+/// the escapes of the dialect (default puzzle). `--encode` writes every line
+/// encoded as a literal of the dialect to the file or, with `-`, to stdout.
+/// The input is streamed, so its size does not matter.
 fn main() {
-    let actions: Vec<u32> = read_input("data/08/input.txt", count);
-    println!("Ergebnis Teil 1: {}", actions.iter().sum::<u32>());
-    let actions: Vec<u32> = read_input("data/08/input.txt", expand);
-    println!("Ergebnis Teil 2: {}", actions.iter().sum::<u32>());
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let input = args.iter().position(|a| a == "--input").and_then(|i| args.get(i + 1));
+    let dialect = match args.iter().position(|a| a == "--dialect").and_then(|i| args.get(i + 1)) {
+        Some(d) => Dialect::parse(d).unwrap_or_else(|| panic!("unknown dialect {}, expected one of {:?}",
+                                                             d, Dialect::ALL.map(Dialect::name))),
+        None => Dialect::Puzzle,
+    };
+    let file = File::open(input.map_or("data/08/input.txt", String::as_str)).expect("no such file");
+    let mut out: Option<BufWriter<Box<dyn Write>>> = args.iter().position(|a| a == "--encode")
+        .and_then(|i| args.get(i + 1))
+        .map(|f| BufWriter::new(if f == "-" {
+            Box::new(io::stdout().lock()) as Box<dyn Write>
+        } else {
+            Box::new(File::create(f).expect("cannot create output file"))
+        }));
+    let mut failed = false;
+    let total = measure_stream(BufReader::new(file), dialect, out.as_mut().map(|w| w as &mut dyn Write),
+                               &mut |line, e| { eprintln!("line {}, {}", line, e); failed = true; })
+        .expect("reading or encoding failed");
+    if failed {
+        std::process::exit(1);
+    }
+    if dialect != Dialect::Puzzle {
+        println!("Dialekt {}: {} Zeichen Code, {} im Speicher, {} kodiert",
+                 dialect.name(), total.code, total.memory, total.encoded);
+    }
+    println!("Ergebnis Teil 1: {}", total.count());
+    println!("Ergebnis Teil 2: {}", total.expand());
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(bytes: &[u8], dialect: Dialect) -> Result<String, LiteralError> {
+        let mut out = Vec::new();
+        write_encoded(&mut out, bytes, dialect).map_err(|_| units(bytes, dialect).err().unwrap())?;
+        assert_eq!(out.len(), encoded_len(bytes, dialect).unwrap());
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn test_example() {
+        let lines = [r#""""#, r#""abc""#, r#""aaa\"aaa""#, r#""\x27""#];
+        let total = lines.iter().map(|l| Lengths::measure(l, Dialect::Puzzle).unwrap()).fold(Lengths::default(), Lengths::add);
+        assert_eq!((total.count(), total.expand()), (12, 19));
+        assert_eq!(decode(r#""aaa\"aaa""#, Dialect::Puzzle).unwrap(), b"aaa\"aaa");
+        assert_eq!(decode(r#""\xff\\""#, Dialect::Puzzle).unwrap(), vec![0xff, b'\\']);
+    }
+
+    #[test]
+    fn test_errors() {
+        let kind = |s: &str, d| decode(s, d).map_err(|e| (e.pos, e.kind)).err();
+        assert_eq!(kind("abc", Dialect::Puzzle), Some((0, ErrorKind::MissingQuote)));
+        assert_eq!(kind(r#""abc"#, Dialect::Puzzle), Some((4, ErrorKind::Unterminated)));
+        assert_eq!(kind(r#""ab\"#, Dialect::Puzzle), Some((3, ErrorKind::Unterminated)));
+        assert_eq!(kind(r#""a\qb""#, Dialect::Puzzle), Some((2, ErrorKind::BadEscape('q'))));
+        assert_eq!(kind(r#""a\x4""#, Dialect::Puzzle), Some((2, ErrorKind::TruncatedHex)));
+        assert_eq!(kind(r#""a\x4g""#, Dialect::Puzzle), Some((2, ErrorKind::TruncatedHex)));
+        assert_eq!(kind(r#""a"b"#, Dialect::Puzzle), Some((3, ErrorKind::Trailing)));
+        assert_eq!(kind(r#""\n""#, Dialect::Puzzle), Some((1, ErrorKind::BadEscape('n'))));
+        assert_eq!(kind(r#""\ud800x""#, Dialect::Json), Some((1, ErrorKind::OutOfRange)));
+        assert_eq!(kind(r#""\x80""#, Dialect::Rust), Some((1, ErrorKind::OutOfRange)));
+        assert_eq!(kind(r#""\u{41""#, Dialect::Rust), Some((1, ErrorKind::TruncatedHex)));
+        assert_eq!(kind(r#""\x100""#, Dialect::C), Some((1, ErrorKind::OutOfRange)));
+        assert_eq!(encode(b"\xff", Dialect::Json).map_err(|e| e.kind), Err(ErrorKind::Unencodable));
+    }
+
+    #[test]
+    fn test_dialects() {
+        let decoded = |s: &str, d| String::from_utf8(decode(s, d).unwrap()).unwrap();
+        assert_eq!(decoded(r#""a\tb\101\x4a\?""#, Dialect::C), "a\tbAJ?");
+        assert_eq!(decoded(r#""é\/😀""#, Dialect::Json), "é/😀");
+        assert_eq!(decoded(r#""\u{1F600}\0\n""#, Dialect::Rust), "😀\0\n");
+        assert_eq!(decoded(r#""\xe9€\U0001f600\q""#, Dialect::Python), "é€😀\\q");
+        let lengths = Lengths::measure(r#""€\n""#, Dialect::Python).unwrap();
+        assert_eq!((lengths.count(), lengths.expand()), (3, 10));
+        let lengths = Lengths::measure("\"€€€\"", Dialect::Puzzle).unwrap();
+        assert_eq!((lengths.code, lengths.memory, lengths.count(), lengths.expand()), (11, 9, 2, 31));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let bytes: Vec<u8> = (0..=255).collect();
+        let text = "a\"b\\c\n\t\r\0\x07\x7fé€😀 x".as_bytes();
+        for dialect in Dialect::ALL {
+            if dialect.is_bytes() {
+                assert_eq!(decode(&encode(&bytes, dialect).unwrap(), dialect).unwrap(), bytes);
+            }
+            assert_eq!(decode(&encode(text, dialect).unwrap(), dialect).unwrap(), text, "{:?}", dialect);
+        }
+        for line in [r#""""#, r#""x\x41\"\\y""#, r#""\xe9t\xE9""#] {
+            let decoded = decode(line, Dialect::Puzzle).unwrap();
+            let canonical = encode(&decoded, Dialect::Puzzle).unwrap();
+            assert_eq!(decode(&canonical, Dialect::Puzzle).unwrap(), decoded);
+            assert_eq!(encode(&decode(&canonical, Dialect::Puzzle).unwrap(), Dialect::Puzzle).unwrap(), canonical);
+        }
+        assert_eq!(encode(b"a\"b\\", Dialect::Puzzle).unwrap(), r#""a\"b\\""#);
+    }
+
+    #[test]
+    fn test_streaming() {
+        let input = "\"abc\"\r\n\"\\x27\"\n\"\"";
+        let mut out = Vec::new();
+        let mut errors = Vec::new();
+        let total = measure_stream(input.as_bytes(), Dialect::Puzzle, Some(&mut out), &mut |l, e| errors.push((l, e))).unwrap();
+        assert_eq!((total.count(), total.expand()), (9, 13));
+        assert!(errors.is_empty());
+        assert_eq!(String::from_utf8(out).unwrap(), "\"\\\"abc\\\"\"\n\"\\\"\\\\x27\\\"\"\n\"\\\"\\\"\"\n");
+        assert_eq!(Dialect::Rust.escape(0x1f600).as_bytes(), b"\\u{1f600}");
+        assert_eq!(Dialect::Json.escape(0x1f600).as_bytes(), b"\\ud83d\\ude00");
+        assert_eq!(Dialect::C.escape(0).as_bytes(), b"\\000");
+
+        let mut errors = Vec::new();
+        let total = measure_stream("\"a\"\n\"b\\q\"\n\"\\xff\"\n".as_bytes(), Dialect::Puzzle, None, &mut |l, e| errors.push((l, e.kind))).unwrap();
+        assert_eq!(total.code, 9);
+        assert_eq!(errors, vec![(2, ErrorKind::BadEscape('q'))]);
+        let total = measure_stream(&b"\"\xff\"\n"[..], Dialect::Puzzle, None, &mut |l, e| errors.push((l, e.kind))).unwrap();
+        assert_eq!(total, Lengths::default());
+        assert_eq!(errors[1], (1, ErrorKind::Unencodable));
+    }
 }
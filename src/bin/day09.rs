@@ -44,158 +44,317 @@
 //! My Answer: 909
 
 
-use std::collections::{HashSet, BTreeSet, HashMap};
+use std::collections::HashMap;
+use std::fmt;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
+use serde::Serialize;
 
 
+/// One line of the input: `A to B = N`, or `A to B = N (oneway)` for a
+/// distance that holds only from A to B.
 #[derive(Clone, Debug, PartialEq)]
-struct Distance{
-    posA: String,
-    posB: String,
+struct Distance {
+    from: String,
+    to: String,
     distance: u32,
+    oneway: bool,
 }
 
 impl Distance {
-    fn new(s: &str) -> Distance {
-        let mut tokenstream = s.split_whitespace();
-        let A = tokenstream.next().unwrap();
-        let B = tokenstream.nth(1).unwrap();
-        let Ok(distance) = tokenstream.nth(1).unwrap().parse::<u32>() else { todo!() };
-        Distance{posA: A.to_owned(), posB: B.to_owned(), distance}
+    fn new(s: &str) -> Result<Distance, String> {
+        let tokens: Vec<&str> = s.split_whitespace().collect();
+        let (from, to, distance, oneway) = match tokens[..] {
+            [a, "to", b, "=", d] => (a, b, d, false),
+            [a, "to", b, "=", d, "(oneway)"] => (a, b, d, true),
+            _ => return Err(format!("cannot parse {:?}, expected \"A to B = N\"", s)),
+        };
+        let distance = distance.parse::<u32>().map_err(|e| format!("distance {:?}: {}", distance, e))?;
+        Ok(Distance{from: from.to_owned(), to: to.to_owned(), distance, oneway})
     }
-
 }
 
-fn create_distanceMap(con: &Vec<Distance>) -> HashMap<String, u32> {
-    let mut map: HashMap<String, u32> = HashMap::new();
-    con.iter().for_each(|c| { map.insert(c.posA.to_owned() + ";" + &c.posB, c.distance); } );
-    con.iter().for_each(|c| { map.insert(c.posB.to_owned() + ";" + &c.posA, c.distance); } );
-    map
+/// Problems with the distances that make routing meaningless.
+#[derive(Clone, Debug, PartialEq)]
+enum InputError {
+    /// A leg from a location to itself.
+    SelfLoop(String),
+    /// Two lines give different distances for the same directed leg.
+    Conflict { from: String, to: String, first: u32, second: u32 },
+    /// No distance between the two locations, in either direction.
+    Missing { a: String, b: String },
+    /// More locations than `held_karp` can handle.
+    TooMany(usize),
+    /// A location in the routing options that is not in the input.
+    Unknown(String),
+    /// A location without any usable leg after forbidding legs.
+    Disconnected(String),
 }
 
-
-fn locations(connections: &Vec<Distance>) -> HashSet<String> {
-    let mut set: HashSet<String> = HashSet::new();
-    for connection in connections.iter() {
-        set.insert(connection.posA.clone());
-        set.insert(connection.posB.clone());
+impl fmt::Display for InputError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InputError::SelfLoop(l) => write!(f, "{} has a distance to itself", l),
+            InputError::Conflict { from, to, first, second } => write!(f, "{} to {} is both {} and {}", from, to, first, second),
+            InputError::Missing { a, b } => write!(f, "no distance between {} and {}", a, b),
+            InputError::TooMany(n) => write!(f, "{} locations, at most {} supported", n, MAX_LOCATIONS),
+            InputError::Unknown(l) => write!(f, "unknown location {}", l),
+            InputError::Disconnected(l) => write!(f, "{} cannot be reached or left", l),
+        }
     }
-    set
 }
 
-/// Calculate the next permutation of a lexicographically sortied sequense
-/// following this schema:
-///
-/// (1) Finding the rightmost element which is smaller than the element to
-///     its right.
-/// (2) Swap that element with the smallest element to its right which is
-///     larger than it.
-/// (3) Reverse the part of the permutation to the right of where that
-///     element was.
-/// 
-/// Both steps (1) and (3) are worst-case O(n), but it is easy to prove that
-/// the average time for those steps is O(1).
-fn permutate<T: PartialOrd>(sequence: &mut Vec<T>) -> bool {
-    let mut rightmost: i32 = -1;
-    let mut swapparty: usize;
-
-    // (1) find rightmust elemet that is less than its right neighbour
-    // Start from the last but one element and run to the first
-    for i in (0..=(sequence.len()-2)).rev() {
-        if sequence[i] < sequence[i+1] {
-            rightmost = i as i32;
-            break;
+/// The locations with ids in order of first appearance, and a dense matrix
+/// of directed distances between them.
+struct Graph {
+    names: Vec<String>,
+    ids: HashMap<String, usize>,
+    dist: Vec<Vec<Option<u32>>>,
+}
+
+impl Graph {
+    /// Interns the locations and fills the matrix. A line without
+    /// `(oneway)` holds in both directions, unless a `(oneway)` line gives
+    /// the reverse direction. Two-way lines contradicting any other line are
+    /// conflicts. All problems are reported at once.
+    fn build(distances: &[Distance]) -> Result<Graph, Vec<InputError>> {
+        let mut names: Vec<String> = Vec::new();
+        let mut ids: HashMap<String, usize> = HashMap::new();
+        let mut intern = |name: &str| *ids.entry(name.to_owned()).or_insert_with(|| {
+            names.push(name.to_owned());
+            names.len() - 1
+        });
+        let legs: Vec<(usize, usize, &Distance)> = distances.iter().map(|d| (intern(&d.from), intern(&d.to), d)).collect();
+        let n = names.len();
+        if n > MAX_LOCATIONS {
+            return Err(vec![InputError::TooMany(n)]);
         }
-    };
-    if rightmost == -1 {
-        // no further permutation possible
-        return false;
-    }
-    // find largest element to the right that is smaller than rightmost
-    swapparty = (rightmost as usize) + 1;
-    for i in (rightmost as usize + 1)..=(sequence.len()-1) {
-        if sequence[i] > sequence[rightmost as usize] {
-            if sequence[i] < sequence[swapparty] {
-                swapparty = i;
+        /// Which kind of line set a distance.
+        #[derive(Clone, Copy, PartialEq)]
+        enum Source { Mirrored, TwoWay, OneWay }
+        let mut errors = Vec::new();
+        let mut dist = vec![vec![None; n]; n];
+        let mut source = vec![vec![Source::Mirrored; n]; n];
+        for &(a, b, d) in &legs {
+            if a == b {
+                errors.push(InputError::SelfLoop(d.from.clone()));
+                continue;
+            }
+            // a one-way line may only override the mirror of a two-way line
+            let overrides = |s: Source| d.oneway && s == Source::Mirrored;
+            let (forward, backward) = (dist[a][b].filter(|_| !overrides(source[a][b])), dist[b][a]);
+            let clash = match (forward, backward) {
+                (Some(first), _) if first != d.distance => Some(first),
+                (_, Some(first)) if !d.oneway && source[b][a] == Source::TwoWay && first != d.distance => Some(first),
+                _ => None,
+            };
+            if let Some(first) = clash {
+                errors.push(InputError::Conflict { from: d.from.clone(), to: d.to.clone(), first, second: d.distance });
+                continue;
+            }
+            dist[a][b] = Some(d.distance);
+            source[a][b] = if d.oneway { Source::OneWay } else { Source::TwoWay };
+            if !d.oneway && backward.is_none() {
+                dist[b][a] = Some(d.distance);
             }
         }
+        errors.extend((0..n).flat_map(|a| (a + 1..n).map(move |b| (a, b)))
+            .filter(|&(a, b)| dist[a][b].is_none() && dist[b][a].is_none())
+            .map(|(a, b)| InputError::Missing{a: names[a].clone(), b: names[b].clone()}));
+        if errors.is_empty() { Ok(Graph{names, ids, dist}) } else { Err(errors) }
+    }
+
+    fn id(&self, name: &str) -> Result<usize, InputError> {
+        self.ids.get(name).copied().ok_or_else(|| InputError::Unknown(name.to_owned()))
     }
-    sequence.swap(rightmost as usize, swapparty);
-    sequence[(rightmost+1) as usize..].reverse();
-    return true;
 }
 
+/// Largest number of locations `held_karp` accepts, its tables grow with
+/// 2^n·n.
+const MAX_LOCATIONS: usize = 20;
+
+/// Marks paths in the Held-Karp table that have not been reached yet. Path
+/// lengths are summed in `u64`, so even `MAX_LOCATIONS` legs of `u32::MAX`
+/// stay far below it.
+const UNREACHED: u64 = u64::MAX;
 
-fn calcDistanceCost(locations: &Vec<String>, locationOrder: &Vec<u32>, distanceCost: &HashMap<String, u32>) -> u32 {
-    let mut distance: u32 = 0;
-    let mut fromIdx: usize = 0;
-    let mut toIdx: usize = 0;
-    let mut distanceKey: String = String::new();
-    
-    for i in 0..locationOrder.len()-1 {
-        fromIdx = locationOrder[i] as usize - 1;
-        toIdx = locationOrder[i+1] as usize - 1;
-        distanceKey.clear();
-        distanceKey.push_str(&locations[fromIdx]);
-        distanceKey.push(';');
-        distanceKey.push_str(&locations[toIdx]);
-        distance += distanceCost.get(&distanceKey).unwrap();
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Goal {
+    Shortest,
+    Longest,
+}
+
+impl Goal {
+    fn improves(self, candidate: u64, best: u64) -> bool {
+        best == UNREACHED || match self {
+            Goal::Shortest => candidate < best,
+            Goal::Longest  => candidate > best,
+        }
     }
-    distance
 }
 
+/// Restrictions on the routes `held_karp` considers, by location index.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Constraints {
+    start: Option<usize>,
+    end: Option<usize>,
+    /// Return to the start after the last location.
+    closed: bool,
+}
 
-fn factorial(num: u32) -> u32 {
-    (1..=num).product()
+/// Finds the shortest or longest route through all locations with the
+/// Held-Karp dynamic programme in O(2^n·n²). `best[set * n + last]` is the
+/// optimal length of a path that visits exactly the locations in the bit set
+/// `set` and ends in `last`, `prev` remembers the location before `last`.
+/// Legs that are `None` in `dist` do not exist. A closed tour repeats its
+/// start at the end of the order.
+fn held_karp(dist: &[Vec<Option<u32>>], goal: Goal, constraints: &Constraints) -> Option<(Vec<usize>, u64)> {
+    let n = dist.len();
+    assert!(n <= MAX_LOCATIONS, "{} locations, at most {} supported", n, MAX_LOCATIONS);
+    if n == 0 {
+        return None;
+    }
+    // every location of a tour can be its start, so a tour starts at its end
+    let start = constraints.start.or(constraints.closed.then(|| constraints.end.unwrap_or(0)));
+    if constraints.closed && constraints.end.is_some_and(|e| Some(e) != start) {
+        return None;
+    }
+    let full = (1usize << n) - 1;
+    let mut best = vec![UNREACHED; (full + 1) * n];
+    let mut prev = vec![0u8; (full + 1) * n];
+    for first in (0..n).filter(|&f| start.is_none_or(|s| s == f)) {
+        best[(1 << first) * n + first] = 0;
+    }
+    for set in 1..=full {
+        for last in 0..n {
+            let len = best[set * n + last];
+            if len == UNREACHED {
+                continue;
+            }
+            for next in (0..n).filter(|next| set & (1 << next) == 0) {
+                let Some(leg) = dist[last][next] else { continue };
+                let candidate = len + leg as u64;
+                let slot = (set | 1 << next) * n + next;
+                if goal.improves(candidate, best[slot]) {
+                    best[slot] = candidate;
+                    prev[slot] = last as u8;
+                }
+            }
+        }
+    }
+    // length of the whole route when it stops at `last`
+    let finish = |last: usize| -> Option<u64> {
+        let len = best[full * n + last];
+        if len == UNREACHED {
+            None
+        } else if constraints.closed {
+            let home = start.unwrap();
+            if n == 1 { Some(0) } else { dist[last][home].map(|leg| len + leg as u64) }
+        } else if constraints.end.is_some_and(|e| e != last) {
+            None
+        } else {
+            Some(len)
+        }
+    };
+    let (last, total) = (0..n).filter_map(|l| finish(l).map(|t| (l, t)))
+        .reduce(|a, b| if goal.improves(b.1, a.1) { b } else { a })?;
+    let mut order = vec![last];
+    let (mut set, mut cur) = (full, last);
+    while set != 1 << cur {
+        let before = prev[set * n + cur] as usize;
+        set &= !(1 << cur);
+        cur = before;
+        order.push(cur);
+    }
+    order.reverse();
+    if constraints.closed && n > 1 {
+        order.push(order[0]);
+    }
+    Some((order, total))
 }
 
+/// Routing options, by location name.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Options {
+    start: Option<String>,
+    end: Option<String>,
+    closed: bool,
+    /// Directed legs the route must not use.
+    forbidden: Vec<(String, String)>,
+}
 
-fn locationsOrder_ToString(locations: &Vec<String>, order: &Vec<u32>) -> String {
-    let mut result: String = locations[0].clone();
-    for i in 1..order.len() {
-        result.push(',');
-        result.push_str(&locations[i]);
+impl Options {
+    /// Forbids the legs of `A to B`, only from A to B with `(oneway)`.
+    fn forbid(&mut self, leg: &str) -> Result<(), String> {
+        match leg.split_whitespace().collect::<Vec<&str>>()[..] {
+            [a, "to", b] => {
+                self.forbidden.push((a.to_owned(), b.to_owned()));
+                self.forbidden.push((b.to_owned(), a.to_owned()));
+            },
+            [a, "to", b, "(oneway)"] => self.forbidden.push((a.to_owned(), b.to_owned())),
+            _ => return Err(format!("cannot parse leg {:?}, expected \"A to B\"", leg)),
+        }
+        Ok(())
     }
-    result
 }
 
 
-fn calc_fastest_path(connections: &Vec<Distance>) -> (String, u32) {
-    let distanceMap: HashMap<String, u32> = create_distanceMap(&connections);
-    let locations: Vec<String> = locations(&connections).drain().collect();
-    let mut locationsOrder: Vec<u32> = (1..=locations.len() as u32).collect();
-    let mut fastestOrder: Vec<u32> = locationsOrder.clone();
-    let mut distance: u32 = calcDistanceCost(&locations, &locationsOrder, &distanceMap);
+/// A trip through all locations, in the order they are visited.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+struct Route {
+    stops: Vec<String>,
+    /// Distance of the leg from `stops[i]` to `stops[i + 1]`.
+    legs: Vec<u32>,
+    total: u64,
+}
 
-    for i in 1..factorial(locations.len() as u32) {
-        permutate(&mut locationsOrder);
-        let mut newdist = calcDistanceCost(&locations, &locationsOrder, &distanceMap);
-        if newdist < distance {
-           distance = newdist;
-           fastestOrder = locationsOrder.clone();
+impl Route {
+    fn new(locations: &[String], order: &[usize], dist: &[Vec<Option<u32>>]) -> Route {
+        let legs: Vec<u32> = order.windows(2).map(|w| dist[w[0]][w[1]].expect("route uses existing legs")).collect();
+        Route {
+            stops: order.iter().map(|&i| locations[i].clone()).collect(),
+            total: legs.iter().map(|&leg| leg as u64).sum(),
+            legs,
         }
     }
-    (locationsOrder_ToString(&locations, &fastestOrder), distance)
 }
 
+/// `Dublin -> London -> Belfast = 605`, the alternate form lists every leg
+/// on its own line.
+impl fmt::Display for Route {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if f.alternate() {
+            for (leg, stops) in self.legs.iter().zip(self.stops.windows(2)) {
+                writeln!(f, "{} -> {} = {}", stops[0], stops[1], leg)?;
+            }
+        }
+        write!(f, "{} = {}", self.stops.join(" -> "), self.total)
+    }
+}
 
-fn calc_slowest_path(connections: &Vec<Distance>) -> (String, u32) {
-    let distanceMap: HashMap<String, u32> = create_distanceMap(&connections);
-    let locations: Vec<String> = locations(&connections).drain().collect();
-    let mut locationsOrder: Vec<u32> = (1..=locations.len() as u32).collect();
-    let mut slowestOrder: Vec<u32> = locationsOrder.clone();
-    let mut distance: u32 = calcDistanceCost(&locations, &locationsOrder, &distanceMap);
+/// Both answers, for the JSON output.
+#[derive(Serialize)]
+struct Trips<'a> {
+    fastest: &'a Route,
+    slowest: &'a Route,
+}
 
-    for i in 1..factorial(locations.len() as u32) {
-        permutate(&mut locationsOrder);
-        let mut newdist = calcDistanceCost(&locations, &locationsOrder, &distanceMap);
-        if newdist > distance {
-           distance = newdist;
-           slowestOrder = locationsOrder.clone();
-        }
+
+fn calc_path(graph: &Graph, goal: Goal, options: &Options) -> Result<Option<Route>, InputError> {
+    let constraints = Constraints {
+        start: options.start.as_deref().map(|l| graph.id(l)).transpose()?,
+        end: options.end.as_deref().map(|l| graph.id(l)).transpose()?,
+        closed: options.closed,
+    };
+    let mut dist = graph.dist.clone();
+    for (a, b) in &options.forbidden {
+        dist[graph.id(a)?][graph.id(b)?] = None;
+    }
+    let n = dist.len();
+    if let Some(l) = (0..n).find(|&l| n > 1 && (0..n).all(|o| dist[l][o].is_none() && dist[o][l].is_none())) {
+        return Err(InputError::Disconnected(graph.names[l].clone()));
     }
-    (locationsOrder_ToString(&locations, &slowestOrder), distance)
+    Ok(held_karp(&dist, goal, &constraints).map(|(order, _)| Route::new(&graph.names, &order, &dist)))
 }
 
 
@@ -210,10 +369,250 @@ where
     y.collect()
 }
 
+/// Usage: day09 [--input FILE] [--start LOCATION] [--end LOCATION] [--closed]
+///              [--forbid "A to B [(oneway)]"]... [--json | --legs]
+///
+/// Finds the shortest and the longest route through all locations. The
+/// route can be pinned to a start and an end, `--closed` returns to the
+/// start, and every `--forbid` removes a leg in both directions or, with
+/// `(oneway)`, only from A to B. `--json` prints both routes as JSON,
+/// `--legs` lists the distance of every leg.
 fn main() {
-    let connections: Vec<Distance> = read_input("data/09/input.txt", Distance::new );
-    let (tour, distance) = calc_fastest_path(&connections);
-    println!("Fastest trip ist {} with distance {}", tour, distance);
-    let (tour, distance) = calc_slowest_path(&connections);
-    println!("slowest trip ist {} with distance {}", tour, distance);
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let input = args.iter().position(|a| a == "--input").and_then(|i| args.get(i + 1));
+    let lines: Vec<Result<Distance, String>> = read_input(input.map_or("data/09/input.txt", String::as_str), Distance::new);
+    if lines.iter().any(Result::is_err) {
+        lines.iter().enumerate().filter_map(|(idx, r)| r.as_ref().err().map(|e| (idx, e)))
+            .for_each(|(idx, e)| eprintln!("line {}: {}", idx + 1, e));
+        std::process::exit(1);
+    }
+    let distances: Vec<Distance> = lines.into_iter().map(Result::unwrap).collect();
+    let graph = Graph::build(&distances).unwrap_or_else(|errors| {
+        errors.iter().for_each(|e| eprintln!("{}", e));
+        std::process::exit(1);
+    });
+    let value = |flag: &str| args.iter().position(|a| a == flag).and_then(|i| args.get(i + 1)).cloned();
+    let mut options = Options {
+        start: value("--start"),
+        end: value("--end"),
+        closed: args.iter().any(|a| a == "--closed"),
+        forbidden: Vec::new(),
+    };
+    for leg in args.windows(2).filter(|w| w[0] == "--forbid").map(|w| &w[1]) {
+        options.forbid(leg).unwrap_or_else(|e| panic!("{}", e));
+    }
+    let trip = |goal| calc_path(&graph, goal, &options)
+        .unwrap_or_else(|e| { eprintln!("{}", e); std::process::exit(1) })
+        .unwrap_or_else(|| { eprintln!("No trip satisfies the routing options"); std::process::exit(1) });
+    let (fastest, slowest) = (trip(Goal::Shortest), trip(Goal::Longest));
+    if args.iter().any(|a| a == "--json") {
+        let trips = Trips{fastest: &fastest, slowest: &slowest};
+        println!("{}", serde_json::to_string_pretty(&trips).expect("routes are serialisable"));
+    } else if args.iter().any(|a| a == "--legs") {
+        println!("Fastest trip ist\n{:#}", fastest);
+        println!("slowest trip ist\n{:#}", slowest);
+    } else {
+        println!("Fastest trip ist {}", fastest);
+        println!("slowest trip ist {}", slowest);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use itertools::Itertools;
+
+    /// Symmetric paths have the same length in both directions.
+    fn either_way(order: &[usize], expected: &[usize]) -> bool {
+        order == expected || order.iter().rev().eq(expected)
+    }
+
+    fn legs(dist: Vec<Vec<u32>>) -> Vec<Vec<Option<u32>>> {
+        dist.into_iter().map(|row| row.into_iter().map(Some).collect()).collect()
+    }
+
+    fn length(order: &[usize], dist: &[Vec<Option<u32>>]) -> Option<u64> {
+        order.windows(2).map(|w| dist[w[0]][w[1]].map(u64::from)).sum()
+    }
+
+    fn brute_force(dist: &[Vec<Option<u32>>], goal: Goal, constraints: &Constraints) -> Option<u64> {
+        let lengths = (0..dist.len()).permutations(dist.len())
+            .filter(|p| constraints.start.is_none_or(|s| p[0] == s))
+            .filter(|p| constraints.end.is_none_or(|e| e == if constraints.closed { p[0] } else { p[p.len() - 1] }))
+            .filter_map(|mut p| {
+                if constraints.closed {
+                    p.push(p[0]);
+                }
+                length(&p, dist)
+            });
+        match goal {
+            Goal::Shortest => lengths.min(),
+            Goal::Longest  => lengths.max(),
+        }
+    }
+
+    /// Deterministic pseudo-random distances, asymmetric unless `symmetric`.
+    fn random_distances(n: usize, symmetric: bool) -> Vec<Vec<Option<u32>>> {
+        let hash = |a: usize, b: usize| ((a * 31 + b) as u32).wrapping_mul(2654435761) >> 22;
+        (0..n).map(|a| (0..n).map(|b| match a.cmp(&b) {
+            std::cmp::Ordering::Equal => None,
+            std::cmp::Ordering::Greater if symmetric => Some(hash(b, a)),
+            _ => Some(hash(a, b)),
+        }).collect()).collect()
+    }
+
+    #[test]
+    fn test_example() {
+        let dist = legs(vec![vec![0, 464, 518], vec![464, 0, 141], vec![518, 141, 0]]);
+        let free = Constraints::default();
+        let (order, total) = held_karp(&dist, Goal::Shortest, &free).unwrap();
+        assert!(either_way(&order, &[0, 1, 2]));
+        assert_eq!(total, 605);
+        let (order, total) = held_karp(&dist, Goal::Longest, &free).unwrap();
+        assert!(either_way(&order, &[1, 0, 2]));
+        assert_eq!(total, 982);
+        assert_eq!(held_karp(&[vec![None]], Goal::Shortest, &free), Some((vec![0], 0)));
+        assert_eq!(held_karp(&[], Goal::Shortest, &free), None);
+    }
+
+    #[test]
+    fn test_route() {
+        let locations = ["Belfast", "Dublin", "London"].map(String::from);
+        let dist = legs(vec![vec![0, 518, 141], vec![518, 0, 464], vec![141, 464, 0]]);
+        let route = Route::new(&locations, &[1, 2, 0], &dist);
+        assert_eq!(route.to_string(), "Dublin -> London -> Belfast = 605");
+        assert_eq!(format!("{:#}", route), "Dublin -> London = 464\nLondon -> Belfast = 141\nDublin -> London -> Belfast = 605");
+        assert_eq!(serde_json::to_string(&route).unwrap(),
+                   r#"{"stops":["Dublin","London","Belfast"],"legs":[464,141],"total":605}"#);
+    }
+
+    #[test]
+    fn test_against_brute_force() {
+        let n = 7;
+        let options = [
+            Constraints::default(),
+            Constraints{start: Some(2), ..Default::default()},
+            Constraints{start: Some(1), end: Some(4), closed: false},
+            Constraints{end: Some(0), ..Default::default()},
+            Constraints{closed: true, ..Default::default()},
+            Constraints{start: Some(3), closed: true, ..Default::default()},
+            Constraints{end: Some(5), closed: true, ..Default::default()},
+        ];
+        for symmetric in [true, false] {
+            let mut dist = random_distances(n, symmetric);
+            for constraints in &options {
+                for goal in [Goal::Shortest, Goal::Longest] {
+                    let (order, total) = held_karp(&dist, goal, constraints).unwrap();
+                    let visited = if constraints.closed { &order[1..] } else { &order[..] };
+                    assert_eq!(visited.iter().sorted().copied().collect::<Vec<_>>(), (0..n).collect::<Vec<_>>());
+                    assert_eq!(length(&order, &dist), Some(total));
+                    assert_eq!(Some(total), brute_force(&dist, goal, constraints), "{:?} {:?}", goal, constraints);
+                }
+            }
+            // forbidden legs
+            dist[0][1] = None;
+            dist[5][2] = None;
+            for goal in [Goal::Shortest, Goal::Longest] {
+                let free = Constraints::default();
+                assert_eq!(held_karp(&dist, goal, &free).map(|r| r.1), brute_force(&dist, goal, &free));
+            }
+        }
+    }
+
+    #[test]
+    fn test_impossible() {
+        let mut dist = legs(vec![vec![0, 1, 2], vec![1, 0, 3], vec![2, 3, 0]]);
+        let same_ends = Constraints{start: Some(1), end: Some(1), closed: false};
+        assert_eq!(held_karp(&dist, Goal::Shortest, &same_ends), None);
+        // a one-way street into 2 that never leads back
+        dist[2][0] = None;
+        dist[2][1] = None;
+        assert_eq!(held_karp(&dist, Goal::Shortest, &Constraints{closed: true, ..Default::default()}), None);
+        assert_eq!(held_karp(&dist, Goal::Shortest, &Constraints{start: Some(2), ..Default::default()}), None);
+        assert_eq!(held_karp(&dist, Goal::Shortest, &Constraints::default()), Some((vec![1, 0, 2], 3)));
+
+        let mut options = Options::default();
+        options.forbid("A to B (oneway)").unwrap();
+        options.forbid("B to C").unwrap();
+        assert_eq!(options.forbidden, [("A", "B"), ("B", "C"), ("C", "B")].map(|(a, b)| (a.to_owned(), b.to_owned())));
+        assert!(options.forbid("A B").is_err());
+    }
+
+    fn graph(text: &str) -> Result<Graph, Vec<InputError>> {
+        let distances: Vec<Distance> = text.lines().map(|l| Distance::new(l).unwrap()).collect();
+        Graph::build(&distances)
+    }
+
+    #[test]
+    fn test_graph() {
+        let g = graph("London to Dublin = 464\nLondon to Belfast = 518\nDublin to Belfast = 141").unwrap();
+        assert_eq!(g.names, ["London", "Dublin", "Belfast"]);
+        assert_eq!(g.dist[2][1], Some(141));
+        let options = Options::default();
+        assert_eq!(calc_path(&g, Goal::Shortest, &options).unwrap().unwrap().total, 605);
+        assert_eq!(calc_path(&g, Goal::Longest, &options).unwrap().unwrap().total, 982);
+
+        let g = graph("A to B = 5 (oneway)\nB to A = 50\nA to C = 7\nB to C = 3").unwrap();
+        assert_eq!((g.dist[0][1], g.dist[1][0]), (Some(5), Some(50)));
+        let route = calc_path(&g, Goal::Shortest, &options).unwrap().unwrap();
+        assert_eq!(route.to_string(), "A -> B -> C = 8");
+
+        let mut options = Options::default();
+        options.forbid("A to C").unwrap();
+        options.forbid("B to C").unwrap();
+        assert_eq!(calc_path(&g, Goal::Shortest, &options).err(), Some(InputError::Disconnected("C".to_owned())));
+        let options = Options{start: Some("D".to_owned()), ..Default::default()};
+        assert_eq!(calc_path(&g, Goal::Shortest, &options).err(), Some(InputError::Unknown("D".to_owned())));
+    }
+
+    #[test]
+    fn test_input_errors() {
+        assert!(Distance::new("A to B 5").is_err());
+        assert!(Distance::new("A to B = x").is_err());
+        assert!(Distance::new("A to B = 5 (twoway)").is_err());
+        let errors = graph("A to B = 5\nA to B = 6\nB to A = 7\nC to C = 1\nC to A = 2").err().unwrap();
+        assert_eq!(errors, vec![
+            InputError::Conflict{from: "A".to_owned(), to: "B".to_owned(), first: 5, second: 6},
+            InputError::Conflict{from: "B".to_owned(), to: "A".to_owned(), first: 5, second: 7},
+            InputError::SelfLoop("C".to_owned()),
+            InputError::Missing{a: "B".to_owned(), b: "C".to_owned()},
+        ]);
+        let conflict = |text: &str| graph(text).err().map(|e| e.iter().map(InputError::to_string).collect::<Vec<_>>());
+        assert_eq!(conflict("A to B = 5\nB to A = 7"), Some(vec!["B to A is both 5 and 7".to_owned()]));
+        assert_eq!(conflict("B to A = 7 (oneway)\nA to B = 5\nB to A = 5"), Some(vec!["B to A is both 7 and 5".to_owned()]));
+        assert_eq!(conflict("A to B = 5\nB to A = 7 (oneway)\nB to A = 8 (oneway)"), Some(vec!["B to A is both 7 and 8".to_owned()]));
+        assert!(graph("A to B = 5\nB to A = 5\nB to A = 7 (oneway)").is_err());
+        assert!(graph("B to A = 7 (oneway)\nA to B = 5").is_ok());
+        let many: String = (0..=MAX_LOCATIONS).map(|i| format!("L{} to L{} = 1\n", i, i + 1)).collect();
+        assert_eq!(graph(&many).err(), Some(vec![InputError::TooMany(MAX_LOCATIONS + 2)]));
+    }
+
+    #[test]
+    fn test_large_distances() {
+        let g = graph("A to B = 4294967000\nB to C = 4294967000\nA to C = 1").unwrap();
+        let options = Options::default();
+        let fastest = calc_path(&g, Goal::Shortest, &options).unwrap().unwrap();
+        assert_eq!(fastest.total, 4294967001);
+        assert!(fastest.stops[1] != "B");
+        let slowest = calc_path(&g, Goal::Longest, &options).unwrap().unwrap();
+        assert_eq!(slowest.total, 8589934000);
+        assert_eq!(slowest.stops[1], "B");
+        let huge = legs(vec![vec![0, u32::MAX], vec![u32::MAX, 0]]);
+        let tour = Constraints{closed: true, ..Default::default()};
+        assert_eq!(held_karp(&huge, Goal::Longest, &tour).map(|r| r.1), Some(2 * u32::MAX as u64));
+    }
+
+    #[test]
+    fn test_many_locations() {
+        // stops on a line: the shortest path walks from one end to the other
+        let n = 16;
+        let dist = legs((0..n).map(|a: u32| (0..n).map(|b: u32| a.abs_diff(b) * 3).collect()).collect());
+        let (order, total) = held_karp(&dist, Goal::Shortest, &Constraints::default()).unwrap();
+        assert_eq!(total, (n as u64 - 1) * 3);
+        assert!(either_way(&order, &(0..n as usize).collect::<Vec<_>>()));
+        let (order, total) = held_karp(&dist, Goal::Shortest, &Constraints{closed: true, ..Default::default()}).unwrap();
+        assert_eq!(total, (n as u64 - 1) * 6);
+        assert_eq!(order.len(), n as usize + 1);
+    }
 }